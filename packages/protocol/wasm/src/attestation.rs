@@ -0,0 +1,132 @@
+use crate::error::{Error, Result};
+use crate::identity::Identity;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Credential type of server-attested credentials, from the private-use range
+pub const ATTESTED_CREDENTIAL_TYPE: u16 = 0xF0C1;
+
+/// Domain separation label prefixed to the signed attestation content
+const ATTESTATION_LABEL: &[u8] = b"opencall-mls attestation v1";
+
+/// A statement by the auth server that `identity` (canonically encoded),
+/// authenticated in the SRP session `session_id`, owns `signature_key` until
+/// `expires_at` (Unix seconds). It is carried as the content of an attested
+/// credential.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Attestation {
+    pub identity: Vec<u8>,
+    pub signature_key: Vec<u8>,
+    pub expires_at: u64,
+    pub session_id: String,
+    signature: Vec<u8>,
+}
+
+impl Attestation {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| Error::InvalidCredential(format!("Bad attestation: {}", e)))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+
+    /// Check the server signature and that the attestation covers
+    /// `signature_key` at time `now`
+    pub fn verify(&self, server_key: &[u8], signature_key: &[u8], now: u64) -> Result<()> {
+        // Only canonical encodings are signed, so every spelling of an
+        // identity is covered by the same attestation
+        let canonical = Identity::parse(&self.identity).map(|identity| identity.encode());
+        if canonical.ok().as_deref() != Some(&self.identity[..]) {
+            return Err(Error::InvalidCredential(
+                "Attestation identity is not canonically encoded".to_string(),
+            ));
+        }
+        let server_key = <[u8; 32]>::try_from(server_key)
+            .ok()
+            .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+            .ok_or_else(|| Error::InvalidCredential("Bad attestation server key".to_string()))?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|_| Error::InvalidCredential("Malformed attestation signature".to_string()))?;
+
+        server_key
+            .verify(&self.signed_content(), &signature)
+            .map_err(|_| Error::InvalidCredential("Bad attestation signature".to_string()))?;
+
+        if self.signature_key != signature_key {
+            return Err(Error::InvalidCredential(
+                "Attestation does not cover the member's signature key".to_string(),
+            ));
+        }
+        if now > self.expires_at {
+            return Err(Error::InvalidCredential("Attestation has expired".to_string()));
+        }
+        Ok(())
+    }
+
+    /// The bytes the server signs, with every variable-length field
+    /// length-prefixed
+    fn signed_content(&self) -> Vec<u8> {
+        let mut content = ATTESTATION_LABEL.to_vec();
+        for field in [&self.identity[..], &self.signature_key[..], self.session_id.as_bytes()] {
+            content.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            content.extend_from_slice(field);
+        }
+        content.extend_from_slice(&self.expires_at.to_be_bytes());
+        content
+    }
+}
+
+/// Issues attested credentials on the auth server once a user has
+/// completed SRP authentication
+#[wasm_bindgen]
+pub struct CredentialIssuer {
+    signing_key: SigningKey,
+}
+
+#[wasm_bindgen]
+impl CredentialIssuer {
+    /// Create an issuer from the server's 32-byte Ed25519 secret key
+    #[wasm_bindgen(constructor)]
+    pub fn new(secret_key: &[u8]) -> Result<CredentialIssuer> {
+        let secret_key = <[u8; 32]>::try_from(secret_key)
+            .map_err(|_| Error::CryptoError("Issuer secret key must be 32 bytes".to_string()))?;
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&secret_key),
+        })
+    }
+
+    /// The public key clients verify attestations with
+    #[wasm_bindgen(getter, js_name = publicKey)]
+    pub fn public_key(&self) -> Vec<u8> {
+        self.signing_key.verifying_key().to_bytes().to_vec()
+    }
+
+    /// Attest that `identity`, authenticated in SRP session `session_id`,
+    /// owns `signature_key` until `expires_at` (Unix seconds). The identity
+    /// is normalized and signed in its canonical encoding.
+    #[wasm_bindgen(js_name = issue)]
+    pub fn issue(
+        &self,
+        identity: String,
+        signature_key: &[u8],
+        expires_at: u64,
+        session_id: String,
+    ) -> Result<Vec<u8>> {
+        let mut attestation = Attestation {
+            identity: identity.parse::<Identity>()?.encode(),
+            signature_key: signature_key.to_vec(),
+            expires_at,
+            session_id,
+            signature: Vec::new(),
+        };
+        attestation.signature = self
+            .signing_key
+            .sign(&attestation.signed_content())
+            .to_bytes()
+            .to_vec();
+        attestation.encode()
+    }
+}
@@ -0,0 +1,100 @@
+use crate::encrypted_storage::{open_record, seal_record, KdfParams, StorageSecret};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// Magic bytes at the start of every backup bundle
+const BACKUP_MAGIC: &[u8; 4] = b"OCIB";
+
+/// Version of the backup bundle layout
+const BACKUP_VERSION: u16 = 2;
+
+/// Associated data slots binding the sealed parts of a bundle
+const BACKUP_NAMESPACE: &str = "backup";
+const CHECK_KEY: &[u8] = b"check";
+const PAYLOAD_KEY: &[u8] = b"payload";
+
+/// Known plaintext sealed in the header to tell a wrong secret from a
+/// tampered payload
+const CHECK_PLAINTEXT: &[u8] = b"opencall-mls-backup";
+
+/// The long-term material of a client that survives losing its storage
+#[derive(Serialize, Deserialize)]
+pub struct IdentityBackup {
+    /// The serialized client state, as produced by `exportState`
+    pub state: Vec<u8>,
+    /// Raw key package records, restored as they were stored
+    pub key_packages: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Keys of the bundled key packages flagged as last resort
+    #[serde(default)]
+    pub last_resort: Vec<Vec<u8>>,
+}
+
+/// Parameters needed to re-derive the backup key, stored in the clear
+#[derive(Serialize, Deserialize)]
+struct BackupHeader {
+    kdf: KdfParams,
+    check: Vec<u8>,
+}
+
+/// Seal `backup` under `secret`. The bundle is
+/// `magic | version (u16) | header length (u32) | header | sealed payload`,
+/// with the header bound to the payload as associated data.
+pub fn seal_backup(backup: &IdentityBackup, secret: &StorageSecret) -> Result<Vec<u8>> {
+    let kdf = KdfParams::generate(secret)?;
+    let cipher = kdf.derive(secret)?;
+    let check = seal_record(&cipher, BACKUP_NAMESPACE, CHECK_KEY, CHECK_PLAINTEXT)?;
+    let header = serde_json::to_vec(&BackupHeader { kdf, check })
+        .map_err(|e| Error::SerializationError(e.to_string()))?;
+
+    let payload = serde_json::to_vec(backup).map_err(|e| Error::SerializationError(e.to_string()))?;
+    let sealed = seal_record(&cipher, BACKUP_NAMESPACE, &payload_key(&header), &payload)?;
+
+    let mut bundle = Vec::with_capacity(10 + header.len() + sealed.len());
+    bundle.extend_from_slice(BACKUP_MAGIC);
+    bundle.extend_from_slice(&BACKUP_VERSION.to_be_bytes());
+    bundle.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    bundle.extend_from_slice(&header);
+    bundle.extend_from_slice(&sealed);
+    Ok(bundle)
+}
+
+/// Open a bundle produced by `seal_backup`. Fails with
+/// `Error::WrongPassphrase` if `secret` does not match and with
+/// `Error::CorruptBackup` if the bundle was altered. Altering the key
+/// derivation parameters changes the derived key, so it is reported as a
+/// wrong secret.
+pub fn open_backup(bundle: &[u8], secret: &StorageSecret) -> Result<IdentityBackup> {
+    if bundle.len() < 10 || &bundle[..4] != BACKUP_MAGIC {
+        return Err(Error::CorruptBackup);
+    }
+
+    let version = u16::from_be_bytes([bundle[4], bundle[5]]);
+    if version != BACKUP_VERSION {
+        return Err(Error::UnsupportedStorageVersion {
+            namespace: BACKUP_NAMESPACE.to_string(),
+            version,
+        });
+    }
+
+    let header_len = u32::from_be_bytes([bundle[6], bundle[7], bundle[8], bundle[9]]) as usize;
+    if bundle.len() - 10 < header_len {
+        return Err(Error::CorruptBackup);
+    }
+    let (header_bytes, sealed) = bundle[10..].split_at(header_len);
+    let header: BackupHeader = serde_json::from_slice(header_bytes).map_err(|_| Error::CorruptBackup)?;
+
+    // The header is untrusted until the check opens, so bound the work it asks for
+    header.kdf.check_bounds().map_err(|_| Error::CorruptBackup)?;
+    let cipher = header.kdf.derive(secret)?;
+    open_record(&cipher, BACKUP_NAMESPACE, CHECK_KEY, &header.check)
+        .map_err(|_| Error::WrongPassphrase)?;
+
+    let payload = open_record(&cipher, BACKUP_NAMESPACE, &payload_key(header_bytes), sealed)
+        .map_err(|_| Error::CorruptBackup)?;
+    serde_json::from_slice(&payload).map_err(|_| Error::CorruptBackup)
+}
+
+/// The associated data slot of the payload, covering the encoded header
+fn payload_key(header: &[u8]) -> Vec<u8> {
+    [PAYLOAD_KEY, header].concat()
+}
@@ -0,0 +1,138 @@
+use crate::error::{Error, Result};
+use crate::storage::{MemoryBackend, StorageBackend, StorageRecord};
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use wasm_bindgen::prelude::*;
+
+/// A record that changed since the last flush. A `None` value is a deletion.
+pub type DirtyRecord = (String, Vec<u8>, Option<Vec<u8>>);
+
+/// A synchronous in-memory cache that remembers which records changed, so an
+/// async store such as IndexedDB can be kept up to date write-behind.
+///
+/// The openmls `StorageProvider` trait is synchronous, so all reads and
+/// writes are served from memory. The JS side periodically calls `flush` to
+/// persist the dirty records and `hydrate` at startup to load them back.
+#[derive(Default)]
+pub struct CachedBackend {
+    cache: MemoryBackend,
+    dirty: RefCell<BTreeSet<(String, Vec<u8>)>>,
+}
+
+impl CachedBackend {
+    /// Create a cache preloaded with persisted records, none of them dirty
+    pub fn hydrate(records: Vec<StorageRecord>) -> Result<Self> {
+        let backend = Self::default();
+        for (namespace, key, value) in records {
+            backend.cache.put(&namespace, &key, &value)?;
+        }
+        Ok(backend)
+    }
+
+    /// Drain the dirty set, returning the current value of every dirty record
+    pub fn take_dirty(&self) -> Result<Vec<DirtyRecord>> {
+        let dirty = std::mem::take(&mut *self.dirty.borrow_mut());
+        dirty
+            .into_iter()
+            .map(|(namespace, key)| {
+                let value = self.cache.get(&namespace, &key)?;
+                Ok((namespace, key, value))
+            })
+            .collect()
+    }
+
+    /// Mark records dirty again after a failed flush
+    pub fn restore_dirty(&self, records: &[DirtyRecord]) {
+        let mut dirty = self.dirty.borrow_mut();
+        for (namespace, key, _) in records {
+            dirty.insert((namespace.clone(), key.clone()));
+        }
+    }
+
+    fn mark_dirty(&self, namespace: &str, key: &[u8]) {
+        self.dirty
+            .borrow_mut()
+            .insert((namespace.to_string(), key.to_vec()));
+    }
+}
+
+impl StorageBackend for CachedBackend {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.cache.get(namespace, key)
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.cache.put(namespace, key, value)?;
+        self.mark_dirty(namespace, key);
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<()> {
+        self.cache.delete(namespace, key)?;
+        self.mark_dirty(namespace, key);
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<StorageRecord>> {
+        self.cache.entries()
+    }
+
+    fn clear(&self) -> Result<()> {
+        for (namespace, key, _) in self.cache.entries()? {
+            self.mark_dirty(&namespace, &key);
+        }
+        self.cache.clear()
+    }
+}
+
+/// Convert dirty records into `{ namespace, key, value }` JS objects, where
+/// `value` is `null` for deleted records
+pub fn dirty_records_to_js(records: &[DirtyRecord]) -> Result<Array> {
+    let array = Array::new();
+    for (namespace, key, value) in records {
+        let object = Object::new();
+        set(&object, "namespace", &JsValue::from_str(namespace))?;
+        set(&object, "key", &Uint8Array::from(key.as_slice()))?;
+        let value = match value {
+            Some(value) => Uint8Array::from(value.as_slice()).into(),
+            None => JsValue::NULL,
+        };
+        set(&object, "value", &value)?;
+        array.push(&object);
+    }
+    Ok(array)
+}
+
+/// Parse an array of `{ namespace, key, value }` JS objects as produced by
+/// `dirty_records_to_js`. Records with a `null` value are skipped.
+pub fn records_from_js(records: &JsValue) -> Result<Vec<StorageRecord>> {
+    if !Array::is_array(records) {
+        return Err(Error::StorageError("Records must be an array".to_string()));
+    }
+
+    let mut parsed = Vec::new();
+    for record in Array::from(records).iter() {
+        let namespace = get(&record, "namespace")?.as_string().ok_or_else(|| {
+            Error::StorageError("Record namespace must be a string".to_string())
+        })?;
+        let key = Uint8Array::new(&get(&record, "key")?).to_vec();
+        let value = get(&record, "value")?;
+        if value.is_null() || value.is_undefined() {
+            continue;
+        }
+        parsed.push((namespace, key, Uint8Array::new(&value).to_vec()));
+    }
+    Ok(parsed)
+}
+
+fn set(object: &Object, name: &str, value: &JsValue) -> Result<()> {
+    Reflect::set(object, &JsValue::from_str(name), value)
+        .map(|_| ())
+        .map_err(|_| Error::SerializationError(format!("Failed to set `{}`", name)))
+}
+
+fn get(object: &JsValue, name: &str) -> Result<JsValue> {
+    Reflect::get(object, &JsValue::from_str(name))
+        .map_err(|_| Error::SerializationError(format!("Failed to read `{}`", name)))
+}
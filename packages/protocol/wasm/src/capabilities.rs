@@ -0,0 +1,137 @@
+use crate::attestation::ATTESTED_CREDENTIAL_TYPE;
+use crate::error::{Error, Result};
+use openmls::prelude::{
+    Capabilities, CredentialType, Extension, ExtensionType, Extensions, UnknownExtension,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Leaf node extension listing the application features a client supports,
+/// from the private-use range
+pub const FEATURES_EXTENSION_TYPE: u16 = 0xF0C2;
+
+/// Group context extension listing the features every member must support
+pub const REQUIRED_FEATURES_EXTENSION_TYPE: u16 = 0xF0C3;
+
+/// First extension type of the private-use range, the only one applications
+/// may define extensions in
+const PRIVATE_USE_START: u16 = 0xF000;
+
+/// What a client advertises in its key packages and leaves: application
+/// features such as `"sframe"` or `"screen-share"`, and app-defined leaf
+/// extensions keyed by extension type
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ClientCapabilities {
+    pub features: BTreeSet<String>,
+    pub extensions: BTreeMap<u16, Vec<u8>>,
+}
+
+impl ClientCapabilities {
+    /// Replace the advertised features
+    pub fn set_features(&mut self, features: Vec<String>) -> Result<()> {
+        self.features = normalize_features(features)?;
+        Ok(())
+    }
+
+    /// Set or, with `None`, remove an app-defined leaf extension
+    pub fn set_extension(&mut self, extension_type: u16, data: Option<Vec<u8>>) -> Result<()> {
+        if extension_type < PRIVATE_USE_START || is_reserved(extension_type) {
+            return Err(Error::InvalidState(format!(
+                "Extension type {:#06x} is not available to applications",
+                extension_type
+            )));
+        }
+
+        match data {
+            Some(data) => self.extensions.insert(extension_type, data),
+            None => self.extensions.remove(&extension_type),
+        };
+        Ok(())
+    }
+
+    /// The leaf capabilities to advertise. They list every extension type
+    /// this client understands, which MLS requires of extensions in use.
+    pub fn leaf_capabilities(&self) -> Capabilities {
+        let extension_types: Vec<ExtensionType> = [FEATURES_EXTENSION_TYPE, REQUIRED_FEATURES_EXTENSION_TYPE]
+            .into_iter()
+            .chain(self.extensions.keys().copied())
+            .map(ExtensionType::Unknown)
+            .collect();
+        let credential_types = [
+            CredentialType::Basic,
+            CredentialType::X509,
+            CredentialType::Other(ATTESTED_CREDENTIAL_TYPE),
+        ];
+
+        Capabilities::new(None, None, Some(&extension_types), None, Some(&credential_types))
+    }
+
+    /// The leaf extensions to advertise: the feature list and the
+    /// app-defined extensions
+    pub fn leaf_extensions(&self) -> Result<Extensions> {
+        let features = encode_features(&self.features)?;
+        let extensions = std::iter::once((FEATURES_EXTENSION_TYPE, features))
+            .chain(self.extensions.iter().map(|(&extension_type, data)| (extension_type, data.clone())))
+            .map(|(extension_type, data)| Extension::Unknown(extension_type, UnknownExtension(data)))
+            .collect();
+
+        Extensions::from_vec(extensions).map_err(|e| Error::OpenMlsError(e.to_string()))
+    }
+}
+
+/// Group context extensions requiring `features` of every member, if any
+pub fn required_features_extensions(features: Vec<String>) -> Result<Option<Extensions>> {
+    let features = normalize_features(features)?;
+    if features.is_empty() {
+        return Ok(None);
+    }
+
+    let extension = Extension::Unknown(
+        REQUIRED_FEATURES_EXTENSION_TYPE,
+        UnknownExtension(encode_features(&features)?),
+    );
+    Ok(Some(Extensions::single(extension)))
+}
+
+/// The features listed in the extension of type `extension_type`, empty if
+/// the extension is absent or unreadable
+pub fn features_in(extensions: &Extensions, extension_type: u16) -> BTreeSet<String> {
+    extensions
+        .unknown(extension_type)
+        .and_then(|extension| serde_json::from_slice(&extension.0).ok())
+        .unwrap_or_default()
+}
+
+/// Check that a member advertising `features` supports all of `required`
+pub fn check_features(features: &BTreeSet<String>, required: &BTreeSet<String>) -> Result<()> {
+    let missing: Vec<String> = required.difference(features).cloned().collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::MissingCapabilities(missing))
+    }
+}
+
+fn normalize_features(features: Vec<String>) -> Result<BTreeSet<String>> {
+    features
+        .into_iter()
+        .map(|feature| {
+            let feature = feature.trim().to_lowercase();
+            if feature.is_empty() {
+                return Err(Error::InvalidState("Capability names must not be empty".to_string()));
+            }
+            Ok(feature)
+        })
+        .collect()
+}
+
+fn encode_features(features: &BTreeSet<String>) -> Result<Vec<u8>> {
+    serde_json::to_vec(features).map_err(|e| Error::SerializationError(e.to_string()))
+}
+
+fn is_reserved(extension_type: u16) -> bool {
+    matches!(
+        extension_type,
+        FEATURES_EXTENSION_TYPE | REQUIRED_FEATURES_EXTENSION_TYPE
+    )
+}
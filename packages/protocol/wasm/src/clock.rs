@@ -0,0 +1,63 @@
+use crate::error::{Error, Result};
+use js_sys::Function;
+use wasm_bindgen::JsValue;
+
+/// How far apart two clocks may be before a key package lifetime check
+/// fails, in seconds
+pub const MAX_CLOCK_SKEW: u64 = 5 * 60;
+
+/// Lifetime of exported key packages unless configured otherwise: 12 weeks
+pub const DEFAULT_KEY_PACKAGE_LIFETIME: u64 = 12 * 7 * 24 * 60 * 60;
+
+/// Source of the current time, in seconds since the Unix epoch.
+///
+/// `SystemTime` is unavailable on wasm32, so time is read from JS
+/// `Date.now()` unless the application installs its own clock, e.g. one
+/// corrected against a server.
+///
+/// openmls checks key package lifetimes against the system clock on its own
+/// when members are added, so an installed clock can make that check
+/// stricter but never accept a key package the system clock rejects.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+/// A clock implemented by a JS function returning milliseconds since the
+/// Unix epoch, like `Date.now`
+pub struct JsClock {
+    callback: Function,
+}
+
+impl JsClock {
+    pub fn new(callback: Function) -> Self {
+        Self { callback }
+    }
+}
+
+impl Clock for JsClock {
+    fn now(&self) -> u64 {
+        // Fall back to the system clock if the callback misbehaves
+        self.callback
+            .call0(&JsValue::NULL)
+            .ok()
+            .and_then(|now| now.as_f64())
+            .map_or_else(system_now, |now| (now / 1000.0) as u64)
+    }
+}
+
+/// The current time according to JS `Date.now()`
+pub fn system_now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
+/// Check that `now` falls within `not_before..=not_after`, allowing for
+/// `MAX_CLOCK_SKEW` between the clocks of the issuer and the checker
+pub fn check_lifetime(now: u64, not_before: u64, not_after: u64) -> Result<()> {
+    if now.saturating_add(MAX_CLOCK_SKEW) < not_before {
+        return Err(Error::KeyPackageNotYetValid { not_before, now });
+    }
+    if now > not_after.saturating_add(MAX_CLOCK_SKEW) {
+        return Err(Error::KeyPackageExpired { not_after, now });
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use crate::error::{Error, Result};
+use openmls::prelude::{Ciphersuite, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+/// Ciphersuite used when none is configured
+pub const DEFAULT_CIPHERSUITE: Ciphersuite =
+    Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+
+/// Ciphersuites that can be selected by name, with their RFC 9420 names
+const CIPHERSUITES: &[(&str, Ciphersuite)] = &[
+    (
+        "MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519",
+        Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519,
+    ),
+    (
+        "MLS_128_DHKEMP256_AES128GCM_SHA256_P256",
+        Ciphersuite::MLS_128_DHKEMP256_AES128GCM_SHA256_P256,
+    ),
+    (
+        "MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519",
+        Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519,
+    ),
+    (
+        "MLS_256_DHKEMX448_AES256GCM_SHA512_Ed448",
+        Ciphersuite::MLS_256_DHKEMX448_AES256GCM_SHA512_Ed448,
+    ),
+    (
+        "MLS_256_DHKEMP521_AES256GCM_SHA512_P521",
+        Ciphersuite::MLS_256_DHKEMP521_AES256GCM_SHA512_P521,
+    ),
+    (
+        "MLS_256_DHKEMX448_CHACHA20POLY1305_SHA512_Ed448",
+        Ciphersuite::MLS_256_DHKEMX448_CHACHA20POLY1305_SHA512_Ed448,
+    ),
+    (
+        "MLS_256_DHKEMP384_AES256GCM_SHA384_P384",
+        Ciphersuite::MLS_256_DHKEMP384_AES256GCM_SHA384_P384,
+    ),
+    (
+        "MLS_256_XWING_CHACHA20POLY1305_SHA256_Ed25519",
+        Ciphersuite::MLS_256_XWING_CHACHA20POLY1305_SHA256_Ed25519,
+    ),
+];
+
+/// Signature schemes that can be selected by name, with their TLS names
+const SIGNATURE_SCHEMES: &[(&str, SignatureScheme)] = &[
+    ("ed25519", SignatureScheme::ED25519),
+    ("ecdsa_secp256r1_sha256", SignatureScheme::ECDSA_SECP256R1_SHA256),
+    ("ecdsa_secp384r1_sha384", SignatureScheme::ECDSA_SECP384R1_SHA384),
+    ("ecdsa_secp521r1_sha512", SignatureScheme::ECDSA_SECP521R1_SHA512),
+    ("ed448", SignatureScheme::ED448),
+];
+
+/// The JS-facing configuration object, e.g.
+/// `{ ciphersuite: "MLS_128_DHKEMP256_AES128GCM_SHA256_P256" }`.
+/// `requiredCapabilities` only applies when creating a group.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MLSConfig {
+    pub ciphersuite: Option<String>,
+    pub signature_scheme: Option<String>,
+    #[serde(default)]
+    pub required_capabilities: Vec<String>,
+}
+
+impl MLSConfig {
+    pub fn from_js(config: &JsValue) -> Result<Self> {
+        if config.is_null() || config.is_undefined() {
+            return Ok(Self::default());
+        }
+
+        serde_wasm_bindgen::from_value(config.clone())
+            .map_err(|e| Error::SerializationError(e.to_string()))
+    }
+}
+
+/// The resolved cryptographic settings of a client or group
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CryptoSettings {
+    pub ciphersuite: Ciphersuite,
+    pub signature_scheme: SignatureScheme,
+}
+
+impl Default for CryptoSettings {
+    fn default() -> Self {
+        Self {
+            ciphersuite: DEFAULT_CIPHERSUITE,
+            signature_scheme: DEFAULT_CIPHERSUITE.signature_algorithm(),
+        }
+    }
+}
+
+impl CryptoSettings {
+    /// Parse a JS configuration object; `undefined` or `null` selects the
+    /// defaults
+    pub fn from_js(config: &JsValue) -> Result<Self> {
+        Self::from_config(&MLSConfig::from_js(config)?)
+    }
+
+    pub fn from_config(config: &MLSConfig) -> Result<Self> {
+        let ciphersuite = match &config.ciphersuite {
+            Some(name) => ciphersuite_from_name(name)?,
+            None => DEFAULT_CIPHERSUITE,
+        };
+
+        // Every MLS ciphersuite fixes the signature scheme, so an explicit
+        // scheme is only accepted when it agrees with the ciphersuite
+        let signature_scheme = ciphersuite.signature_algorithm();
+        if let Some(name) = &config.signature_scheme {
+            let requested = signature_scheme_from_name(name)?;
+            if requested != signature_scheme {
+                return Err(Error::UnsupportedCiphersuite(format!(
+                    "Signature scheme {} cannot be used with {}",
+                    name,
+                    ciphersuite_name(ciphersuite)
+                )));
+            }
+        }
+
+        Ok(Self {
+            ciphersuite,
+            signature_scheme,
+        })
+    }
+}
+
+pub fn ciphersuite_from_name(name: &str) -> Result<Ciphersuite> {
+    CIPHERSUITES
+        .iter()
+        .find(|(candidate, _)| *candidate == name)
+        .map(|(_, ciphersuite)| *ciphersuite)
+        .ok_or_else(|| Error::UnsupportedCiphersuite(name.to_string()))
+}
+
+pub fn ciphersuite_name(ciphersuite: Ciphersuite) -> String {
+    CIPHERSUITES
+        .iter()
+        .find(|(_, candidate)| *candidate == ciphersuite)
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| format!("{:?}", ciphersuite))
+}
+
+fn signature_scheme_from_name(name: &str) -> Result<SignatureScheme> {
+    SIGNATURE_SCHEMES
+        .iter()
+        .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+        .map(|(_, scheme)| *scheme)
+        .ok_or_else(|| Error::UnsupportedCiphersuite(format!("Unknown signature scheme {}", name)))
+}
@@ -0,0 +1,336 @@
+use crate::attestation::{Attestation, ATTESTED_CREDENTIAL_TYPE};
+use crate::clock::{system_now, Clock};
+use crate::error::{Error, Result};
+use der::asn1::{PrintableStringRef, Utf8StringRef};
+use der::{Decode, Encode};
+use openmls::prelude::{Credential, SignatureScheme};
+use x509_cert::ext::pkix::BasicConstraints;
+use x509_cert::spki::ObjectIdentifier;
+use x509_cert::Certificate;
+
+const ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+const COMMON_NAME: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.3");
+
+/// Root certificates that member X.509 chains must lead to
+#[derive(Default)]
+pub struct TrustAnchors {
+    roots: Vec<Certificate>,
+}
+
+impl TrustAnchors {
+    /// Add a DER-encoded root certificate
+    pub fn add(&mut self, der: &[u8]) -> Result<()> {
+        self.roots.push(parse_certificate(der)?);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.roots.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+}
+
+/// Application hook deciding whether a member may join a group.
+///
+/// It is called for every new member when a Welcome is processed, when this
+/// client adds a member, and when a commit adding members is staged.
+/// Returning an error vetoes the operation with the given reason.
+pub trait CredentialValidator {
+    fn validate(
+        &self,
+        group_id: &[u8],
+        credential: &Credential,
+        signature_key: &[u8],
+    ) -> std::result::Result<(), String>;
+}
+
+/// Decides which credentials are acceptable for group members.
+///
+/// Without trust anchors or an attestation key nothing can be verified, so
+/// basic and attested credentials alike are accepted unverified. Once either is configured every member must present
+/// an X.509 chain that leads to an anchor, or an attestation signed by the
+/// auth server, certifying the member's signature key. Members passing these
+/// checks are then handed to the application `validator`. Certificates,
+/// attestations and key package lifetimes are checked against `clock`, or
+/// the system clock if none is installed.
+#[derive(Default)]
+pub struct CredentialPolicy {
+    pub trust_anchors: TrustAnchors,
+    pub attestation_key: Option<Vec<u8>>,
+    pub validator: Option<Box<dyn CredentialValidator>>,
+    pub clock: Option<Box<dyn Clock>>,
+}
+
+impl CredentialPolicy {
+    /// The current time in seconds since the Unix epoch
+    pub fn now(&self) -> u64 {
+        self.clock.as_ref().map_or_else(system_now, |clock| clock.now())
+    }
+
+    /// Check the credential of a member of `group_id` with `signature_key`
+    pub fn validate(
+        &self,
+        group_id: &[u8],
+        credential: &Credential,
+        signature_key: &[u8],
+    ) -> Result<()> {
+        self.check_trust(credential, signature_key)?;
+
+        match &self.validator {
+            Some(validator) => validator
+                .validate(group_id, credential, signature_key)
+                .map_err(Error::CredentialRejected),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether credentials are verified at all, which takes a trust anchor
+    /// or an attestation key
+    pub fn authenticates(&self) -> bool {
+        !self.trust_anchors.is_empty() || self.attestation_key.is_some()
+    }
+
+    /// Check only that the credential certifies `signature_key` as required,
+    /// without consulting the application validator
+    pub fn check_trust(&self, credential: &Credential, signature_key: &[u8]) -> Result<()> {
+        match credential {
+            Credential::Basic(_) if !self.authenticates() => Ok(()),
+            Credential::Other(ATTESTED_CREDENTIAL_TYPE, content) if !self.authenticates() => {
+                Attestation::decode(content).map(|_| ())
+            }
+            Credential::Basic(_) => Err(Error::InvalidCredential(
+                "Basic credentials are not accepted when member authentication is required"
+                    .to_string(),
+            )),
+            Credential::X509(chain) => {
+                verify_chain(chain, signature_key, &self.trust_anchors, self.now())
+            }
+            Credential::Other(ATTESTED_CREDENTIAL_TYPE, content) => {
+                let server_key = self.attestation_key.as_ref().ok_or_else(|| {
+                    Error::InvalidCredential("No attestation key configured".to_string())
+                })?;
+                Attestation::decode(content)?.verify(server_key, signature_key, self.now())
+            }
+            _ => Err(Error::InvalidCredential("Unsupported credential type".to_string())),
+        }
+    }
+}
+
+/// The identity a credential asserts: the basic identity bytes, the common
+/// name of the leaf certificate, or the attested identity
+pub fn credential_identity(credential: &Credential) -> Result<Vec<u8>> {
+    match credential {
+        Credential::Basic(identity) => Ok(identity.clone()),
+        Credential::Other(ATTESTED_CREDENTIAL_TYPE, content) => {
+            Ok(Attestation::decode(content)?.identity)
+        }
+        Credential::X509(chain) => {
+            let leaf = chain
+                .first()
+                .ok_or_else(|| Error::InvalidCredential("Empty certificate chain".to_string()))?;
+            Ok(common_name(&parse_certificate(leaf)?)?.into_bytes())
+        }
+        _ => Err(Error::InvalidCredential("Unsupported credential type".to_string())),
+    }
+}
+
+/// A short name for the kind of credential, as reported to applications
+pub fn credential_type_name(credential: &Credential) -> &'static str {
+    match credential {
+        Credential::Basic(_) => "basic",
+        Credential::X509(_) => "x509",
+        Credential::Other(ATTESTED_CREDENTIAL_TYPE, _) => "attested",
+        _ => "other",
+    }
+}
+
+/// The signature scheme of the key certified by a DER-encoded certificate
+/// and the raw public key, in the encoding MLS uses for leaf nodes
+pub fn certified_key(der: &[u8]) -> Result<(SignatureScheme, Vec<u8>)> {
+    let certificate = parse_certificate(der)?;
+    let spki = &certificate.tbs_certificate.subject_public_key_info;
+    let scheme = match spki.algorithm.oid {
+        ED25519 => SignatureScheme::ED25519,
+        // The curve is named by the algorithm parameters
+        EC_PUBLIC_KEY => match spki.algorithm.parameters_oid() {
+            Ok(SECP256R1) => SignatureScheme::ECDSA_SECP256R1_SHA256,
+            Ok(curve) => {
+                return Err(Error::InvalidCredential(format!(
+                    "Unsupported certificate key curve {}",
+                    curve
+                )))
+            }
+            Err(_) => {
+                return Err(Error::InvalidCredential(
+                    "Certificate EC key does not name its curve".to_string(),
+                ))
+            }
+        },
+        oid => {
+            return Err(Error::InvalidCredential(format!(
+                "Unsupported certificate key algorithm {}",
+                oid
+            )))
+        }
+    };
+    Ok((scheme, spki.subject_public_key.raw_bytes().to_vec()))
+}
+
+/// Verify that `chain` (leaf first) is currently valid, leads to one of
+/// `anchors` and certifies `signature_key`
+pub fn verify_chain(
+    chain: &[Vec<u8>],
+    signature_key: &[u8],
+    anchors: &TrustAnchors,
+    now: u64,
+) -> Result<()> {
+    if anchors.is_empty() {
+        return Err(Error::InvalidCredential("No trust anchors configured".to_string()));
+    }
+
+    let certificates = chain
+        .iter()
+        .map(|der| parse_certificate(der))
+        .collect::<Result<Vec<_>>>()?;
+    let leaf = certificates
+        .first()
+        .ok_or_else(|| Error::InvalidCredential("Empty certificate chain".to_string()))?;
+
+    let certified = leaf.tbs_certificate.subject_public_key_info.subject_public_key.raw_bytes();
+    if certified != signature_key {
+        return Err(Error::InvalidCredential(
+            "Leaf certificate does not certify the member's signature key".to_string(),
+        ));
+    }
+
+    for (index, certificate) in certificates.iter().enumerate() {
+        check_validity(certificate, now)?;
+        if index > 0 && !is_ca(certificate)? {
+            return Err(Error::InvalidCredential(format!(
+                "Intermediate certificate {} is not a CA",
+                index
+            )));
+        }
+    }
+
+    for pair in certificates.windows(2) {
+        verify_issued_by(&pair[0], &pair[1])?;
+    }
+
+    // The top of the chain is either an anchor itself or issued by one
+    let top = certificates.last().expect("chain is not empty");
+    let anchored = anchors.roots.iter().any(|root| {
+        root == top || (check_validity(root, now).is_ok() && verify_issued_by(top, root).is_ok())
+    });
+    if !anchored {
+        return Err(Error::InvalidCredential(
+            "Certificate chain does not lead to a trust anchor".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn parse_certificate(der: &[u8]) -> Result<Certificate> {
+    Certificate::from_der(der).map_err(|e| Error::InvalidCredential(format!("Bad certificate: {}", e)))
+}
+
+fn check_validity(certificate: &Certificate, now: u64) -> Result<()> {
+    let validity = &certificate.tbs_certificate.validity;
+    let not_before = validity.not_before.to_unix_duration().as_secs();
+    let not_after = validity.not_after.to_unix_duration().as_secs();
+    if now < not_before || now > not_after {
+        return Err(Error::InvalidCredential(format!(
+            "Certificate for {} is outside its validity period",
+            certificate.tbs_certificate.subject
+        )));
+    }
+    Ok(())
+}
+
+fn is_ca(certificate: &Certificate) -> Result<bool> {
+    let constraints = certificate
+        .tbs_certificate
+        .get::<BasicConstraints>()
+        .map_err(|e| Error::InvalidCredential(format!("Bad basic constraints: {}", e)))?;
+    Ok(constraints.map_or(false, |(_, constraints)| constraints.ca))
+}
+
+/// Check that `issuer` signed `certificate`
+fn verify_issued_by(certificate: &Certificate, issuer: &Certificate) -> Result<()> {
+    if certificate.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+        return Err(Error::InvalidCredential(format!(
+            "{} was not issued by {}",
+            certificate.tbs_certificate.subject, issuer.tbs_certificate.subject
+        )));
+    }
+
+    let tbs = certificate
+        .tbs_certificate
+        .to_der()
+        .map_err(|e| Error::InvalidCredential(e.to_string()))?;
+    let signature = certificate
+        .signature
+        .as_bytes()
+        .ok_or_else(|| Error::InvalidCredential("Malformed certificate signature".to_string()))?;
+    let issuer_key = issuer
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+        .raw_bytes();
+
+    let verified = match certificate.signature_algorithm.oid {
+        ED25519 => {
+            use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+            let key = <[u8; 32]>::try_from(issuer_key)
+                .ok()
+                .and_then(|key| VerifyingKey::from_bytes(&key).ok());
+            let signature = Signature::from_slice(signature).ok();
+            matches!((key, signature), (Some(key), Some(signature)) if key.verify(&tbs, &signature).is_ok())
+        }
+        ECDSA_WITH_SHA256 => {
+            use p256::ecdsa::signature::Verifier;
+            use p256::ecdsa::{Signature, VerifyingKey};
+            let key = VerifyingKey::from_sec1_bytes(issuer_key).ok();
+            let signature = Signature::from_der(signature).ok();
+            matches!((key, signature), (Some(key), Some(signature)) if key.verify(&tbs, &signature).is_ok())
+        }
+        oid => {
+            return Err(Error::InvalidCredential(format!(
+                "Unsupported certificate signature algorithm {}",
+                oid
+            )))
+        }
+    };
+
+    if !verified {
+        return Err(Error::InvalidCredential(format!(
+            "Bad signature on certificate for {}",
+            certificate.tbs_certificate.subject
+        )));
+    }
+    Ok(())
+}
+
+fn common_name(certificate: &Certificate) -> Result<String> {
+    certificate
+        .tbs_certificate
+        .subject
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attribute| attribute.oid == COMMON_NAME)
+        .and_then(|attribute| {
+            Utf8StringRef::try_from(&attribute.value)
+                .map(|name| name.to_string())
+                .or_else(|_| PrintableStringRef::try_from(&attribute.value).map(|name| name.to_string()))
+                .ok()
+        })
+        .ok_or_else(|| Error::InvalidCredential("Leaf certificate has no common name".to_string()))
+}
@@ -0,0 +1,360 @@
+use crate::error::{Error, Result};
+use crate::storage::{StorageBackend, StorageRecord};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use wasm_bindgen::JsValue;
+use zeroize::Zeroizing;
+
+/// Namespace holding the encryption header, which is never itself encrypted
+const HEADER_NAMESPACE: &str = "encryption";
+const HEADER_KEY: &[u8] = b"header";
+
+/// Namespace holding records re-sealed under a new secret while a rekey is
+/// in progress, keyed by their encoded namespace and key
+const STAGING_NAMESPACE: &str = "encryption_staging";
+
+/// Known plaintext sealed in the header to detect a wrong passphrase
+const CHECK_PLAINTEXT: &[u8] = b"opencall-mls-storage";
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Argon2id cost parameters (memory in KiB, iterations, parallelism)
+const ARGON2_M_COST: u32 = 19 * 1024;
+const ARGON2_T_COST: u32 = 2;
+const ARGON2_P_COST: u32 = 1;
+
+/// Highest Argon2id costs accepted from a header read back, so that a crafted
+/// header cannot make key derivation exhaust memory or time
+const MAX_ARGON2_M_COST: u32 = 256 * 1024;
+const MAX_ARGON2_T_COST: u32 = 16;
+const MAX_ARGON2_P_COST: u32 = 4;
+
+/// The secret protecting an encrypted storage
+pub enum StorageSecret {
+    /// A user passphrase, stretched with Argon2id
+    Passphrase(String),
+    /// A 32-byte key-encryption key supplied by the application, used as is
+    Key(Vec<u8>),
+}
+
+impl StorageSecret {
+    /// Accept either a passphrase string or a 32-byte `Uint8Array` from JS
+    pub fn from_js(secret: &JsValue) -> Result<Self> {
+        if let Some(passphrase) = secret.as_string() {
+            return Ok(StorageSecret::Passphrase(passphrase));
+        }
+
+        if secret.is_instance_of::<js_sys::Uint8Array>() {
+            return Ok(StorageSecret::Key(js_sys::Uint8Array::new(secret).to_vec()));
+        }
+
+        Err(Error::CryptoError(
+            "Storage secret must be a passphrase string or a Uint8Array key".to_string(),
+        ))
+    }
+}
+
+/// Parameters needed to re-derive the storage key, stored in the clear
+#[derive(Serialize, Deserialize)]
+struct EncryptionHeader {
+    kdf: KdfParams,
+    check: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) enum KdfParams {
+    Argon2id {
+        salt: Vec<u8>,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+    Raw,
+}
+
+impl KdfParams {
+    /// Fresh parameters suitable for `secret`
+    pub(crate) fn generate(secret: &StorageSecret) -> Result<Self> {
+        match secret {
+            StorageSecret::Passphrase(_) => Ok(KdfParams::Argon2id {
+                salt: random_bytes(SALT_LEN)?,
+                m_cost: ARGON2_M_COST,
+                t_cost: ARGON2_T_COST,
+                p_cost: ARGON2_P_COST,
+            }),
+            StorageSecret::Key(_) => Ok(KdfParams::Raw),
+        }
+    }
+
+    /// Check that the costs are within what this client is willing to spend
+    pub(crate) fn check_bounds(&self) -> Result<()> {
+        match self {
+            KdfParams::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+                ..
+            } if *m_cost > MAX_ARGON2_M_COST || *t_cost > MAX_ARGON2_T_COST || *p_cost > MAX_ARGON2_P_COST => {
+                Err(Error::CryptoError("Key derivation costs are too high".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn derive(&self, secret: &StorageSecret) -> Result<XChaCha20Poly1305> {
+        // Wiped on drop, whichever way this returns
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        match (self, secret) {
+            (
+                KdfParams::Argon2id {
+                    salt,
+                    m_cost,
+                    t_cost,
+                    p_cost,
+                },
+                StorageSecret::Passphrase(passphrase),
+            ) => {
+                let params = Params::new(*m_cost, *t_cost, *p_cost, Some(KEY_LEN))
+                    .map_err(|e| Error::CryptoError(e.to_string()))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+                    .map_err(|e| Error::CryptoError(e.to_string()))?;
+            }
+            (KdfParams::Raw, StorageSecret::Key(raw)) => {
+                if raw.len() != KEY_LEN {
+                    return Err(Error::CryptoError(format!(
+                        "Key-encryption key must be {} bytes",
+                        KEY_LEN
+                    )));
+                }
+                key.copy_from_slice(raw);
+            }
+            _ => return Err(Error::WrongPassphrase),
+        }
+
+        XChaCha20Poly1305::new_from_slice(key.as_ref()).map_err(|e| Error::CryptoError(e.to_string()))
+    }
+}
+
+/// A storage backend that seals every record with XChaCha20-Poly1305 before
+/// handing it to the inner backend. The namespace and key are bound to each
+/// record as associated data, so records cannot be swapped between slots.
+pub struct EncryptedBackend {
+    inner: Box<dyn StorageBackend>,
+    cipher: RefCell<XChaCha20Poly1305>,
+}
+
+impl EncryptedBackend {
+    /// Open the encrypted store held by `inner`, initializing it on first use.
+    /// Fails with `Error::WrongPassphrase` if `secret` does not match.
+    pub fn open(inner: impl StorageBackend + 'static, secret: &StorageSecret) -> Result<Self> {
+        let cipher = match inner.get(HEADER_NAMESPACE, HEADER_KEY)? {
+            Some(header) => {
+                let header: EncryptionHeader = serde_json::from_slice(&header)
+                    .map_err(|e| Error::StorageError(e.to_string()))?;
+                header.kdf.check_bounds()?;
+                let cipher = header.kdf.derive(secret)?;
+                open_record(&cipher, HEADER_NAMESPACE, HEADER_KEY, &header.check)
+                    .map_err(|_| Error::WrongPassphrase)?;
+                recover_rekey(&inner, &cipher)?;
+                cipher
+            }
+            None => {
+                let (header, cipher) = new_header(secret)?;
+                inner.put(HEADER_NAMESPACE, HEADER_KEY, &header)?;
+                cipher
+            }
+        };
+
+        Ok(Self {
+            inner: Box::new(inner),
+            cipher: RefCell::new(cipher),
+        })
+    }
+
+    /// Re-encrypt every record under a new secret.
+    ///
+    /// Records are first re-sealed into a staging namespace and the new
+    /// header is written last, so the store opens under the old secret until
+    /// the header is replaced and under the new one afterwards. A rekey
+    /// interrupted after the swap is completed by the next `open`.
+    pub fn rekey(&self, secret: &StorageSecret) -> Result<()> {
+        let records = self.entries()?;
+        let (header, cipher) = new_header(secret)?;
+
+        let staged = records.iter().try_for_each(|(namespace, key, value)| {
+            let sealed = seal_record(&cipher, namespace, key, value)?;
+            self.inner.put(STAGING_NAMESPACE, &staging_key(namespace, key)?, &sealed)
+        });
+        if let Err(e) = staged {
+            // Best effort: leftovers are discarded by the next `open` anyway
+            let _ = discard_staging(self.inner.as_ref());
+            return Err(e);
+        }
+
+        self.inner.put(HEADER_NAMESPACE, HEADER_KEY, &header)?;
+        *self.cipher.borrow_mut() = cipher;
+
+        finish_rekey(self.inner.as_ref())
+    }
+}
+
+impl StorageBackend for EncryptedBackend {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner
+            .get(namespace, key)?
+            .map(|sealed| open_record(&self.cipher.borrow(), namespace, key, &sealed))
+            .transpose()
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let sealed = seal_record(&self.cipher.borrow(), namespace, key, value)?;
+        self.inner.put(namespace, key, &sealed)
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<()> {
+        self.inner.delete(namespace, key)
+    }
+
+    fn entries(&self) -> Result<Vec<StorageRecord>> {
+        let cipher = self.cipher.borrow();
+        self.inner
+            .entries()?
+            .into_iter()
+            .filter(|(namespace, _, _)| namespace != HEADER_NAMESPACE && namespace != STAGING_NAMESPACE)
+            .map(|(namespace, key, sealed)| {
+                let value = open_record(&cipher, &namespace, &key, &sealed)?;
+                Ok((namespace, key, value))
+            })
+            .collect()
+    }
+
+    fn clear(&self) -> Result<()> {
+        // Keep the header so the store stays bound to the same secret
+        for (namespace, key, _) in self.inner.entries()? {
+            if namespace != HEADER_NAMESPACE {
+                self.inner.delete(&namespace, &key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Derive a cipher for `secret` with fresh parameters, returning it with the
+/// encoded header to persist
+fn new_header(secret: &StorageSecret) -> Result<(Vec<u8>, XChaCha20Poly1305)> {
+    let kdf = KdfParams::generate(secret)?;
+    let cipher = kdf.derive(secret)?;
+    let check = seal_record(&cipher, HEADER_NAMESPACE, HEADER_KEY, CHECK_PLAINTEXT)?;
+
+    let header = serde_json::to_vec(&EncryptionHeader { kdf, check })
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+    Ok((header, cipher))
+}
+
+/// Resolve a rekey interrupted before it finished. Staged records that open
+/// under the current header were sealed after the swap and are moved into
+/// place; otherwise the swap never happened and they are discarded.
+fn recover_rekey(inner: &(impl StorageBackend + ?Sized), cipher: &XChaCha20Poly1305) -> Result<()> {
+    let staged = staged_records(inner)?;
+    let Some((_, namespace, key, sealed)) = staged.first() else {
+        return Ok(());
+    };
+
+    if open_record(cipher, namespace, key, sealed).is_ok() {
+        finish_rekey(inner)
+    } else {
+        discard_staging(inner)
+    }
+}
+
+/// Move staged records into place, replacing their old sealed values
+fn finish_rekey(inner: &(impl StorageBackend + ?Sized)) -> Result<()> {
+    for (staging_key, namespace, key, sealed) in staged_records(inner)? {
+        inner.put(&namespace, &key, &sealed)?;
+        inner.delete(STAGING_NAMESPACE, &staging_key)?;
+    }
+    Ok(())
+}
+
+fn discard_staging(inner: &(impl StorageBackend + ?Sized)) -> Result<()> {
+    for (staging_key, _, _, _) in staged_records(inner)? {
+        inner.delete(STAGING_NAMESPACE, &staging_key)?;
+    }
+    Ok(())
+}
+
+/// Staged records as (staging key, namespace, key, sealed value)
+fn staged_records(
+    inner: &(impl StorageBackend + ?Sized),
+) -> Result<Vec<(Vec<u8>, String, Vec<u8>, Vec<u8>)>> {
+    inner
+        .entries()?
+        .into_iter()
+        .filter(|(namespace, _, _)| namespace == STAGING_NAMESPACE)
+        .map(|(_, staging_key, sealed)| {
+            let (namespace, key): (String, Vec<u8>) = serde_json::from_slice(&staging_key)
+                .map_err(|e| Error::StorageError(e.to_string()))?;
+            Ok((staging_key, namespace, key, sealed))
+        })
+        .collect()
+}
+
+fn staging_key(namespace: &str, key: &[u8]) -> Result<Vec<u8>> {
+    serde_json::to_vec(&(namespace, key)).map_err(|e| Error::StorageError(e.to_string()))
+}
+
+pub(crate) fn seal_record(
+    cipher: &XChaCha20Poly1305,
+    namespace: &str,
+    key: &[u8],
+    value: &[u8],
+) -> Result<Vec<u8>> {
+    let nonce = random_bytes(NONCE_LEN)?;
+    let aad = associated_data(namespace, key);
+
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg: value, aad: &aad })
+        .map_err(|e| Error::CryptoError(e.to_string()))?;
+
+    let mut sealed = nonce;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub(crate) fn open_record(
+    cipher: &XChaCha20Poly1305,
+    namespace: &str,
+    key: &[u8],
+    sealed: &[u8],
+) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(Error::CryptoError("Sealed record is truncated".to_string()));
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let aad = associated_data(namespace, key);
+
+    cipher
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| Error::CryptoError(format!("Failed to open record in `{}`", namespace)))
+}
+
+fn associated_data(namespace: &str, key: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(namespace.len() + 1 + key.len());
+    aad.extend_from_slice(namespace.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(key);
+    aad
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    getrandom::getrandom(&mut bytes).map_err(|e| Error::CryptoError(e.to_string()))?;
+    Ok(bytes)
+}
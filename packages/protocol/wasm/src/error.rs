@@ -9,8 +9,11 @@ pub enum Error {
     #[error("Invalid state: {0}")]
     InvalidState(String),
     
-    #[error("Member not found: {0}")]
-    MemberNotFound(String),
+    #[error("Member not found: {query}{}", ambiguous_matches(.candidates))]
+    MemberNotFound { query: String, candidates: Vec<String> },
+    
+    #[error("Invalid identity: {0}")]
+    InvalidIdentity(String),
     
     #[error("Invalid message type: {0}")]
     InvalidMessageType(String),
@@ -24,8 +27,38 @@ pub enum Error {
     #[error("Storage error: {0}")]
     StorageError(String),
     
+    #[error("Unsupported storage version {version} in `{namespace}`")]
+    UnsupportedStorageVersion { namespace: String, version: u16 },
+    
     #[error("Crypto error: {0}")]
     CryptoError(String),
+    
+    #[error("Unsupported ciphersuite: {0}")]
+    UnsupportedCiphersuite(String),
+    
+    #[error("Ciphersuite mismatch: expected {expected}, found {found}")]
+    CiphersuiteMismatch { expected: String, found: String },
+    
+    #[error("Invalid credential: {0}")]
+    InvalidCredential(String),
+    
+    #[error("Credential rejected: {0}")]
+    CredentialRejected(String),
+    
+    #[error("Wrong passphrase or key-encryption key for encrypted storage")]
+    WrongPassphrase,
+    
+    #[error("Backup bundle is corrupt or has been tampered with")]
+    CorruptBackup,
+    
+    #[error("Key package expired at {not_after} (now {now})")]
+    KeyPackageExpired { not_after: u64, now: u64 },
+    
+    #[error("Key package is not valid before {not_before} (now {now})")]
+    KeyPackageNotYetValid { not_before: u64, now: u64 },
+    
+    #[error("Missing required capabilities: {}", .0.join(", "))]
+    MissingCapabilities(Vec<String>),
 }
 
 impl From<Error> for JsValue {
@@ -64,4 +97,13 @@ impl From<openmls::prelude::TlsCodecError> for Error {
     }
 }
 
+/// Describe the members an ambiguous query matched, if any
+fn ambiguous_matches(candidates: &[String]) -> String {
+    if candidates.is_empty() {
+        String::new()
+    } else {
+        format!(" (ambiguous, matches {})", candidates.join(", "))
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
@@ -0,0 +1,76 @@
+use sha2::{Digest, Sha512};
+
+/// Version of the fingerprint derivation, part of every hash and QR payload
+pub const FINGERPRINT_VERSION: u16 = 0;
+
+/// Hash iterations, making it expensive to grind keys towards a collision
+/// on the displayed digits
+const ITERATIONS: usize = 5200;
+
+/// Length of a fingerprint in bytes; the numeric form shows 30 digits
+pub const FINGERPRINT_LEN: usize = 32;
+
+/// Digits per group of the numeric form and number of groups
+const DIGITS_PER_CHUNK: usize = 5;
+const CHUNKS: usize = 6;
+
+/// Domain separation label of the whole-group verification code
+const GROUP_CODE_LABEL: &[u8] = b"opencall-mls group verification code";
+
+/// Fingerprint of a member's serialized credential and signature key
+pub fn member_fingerprint(credential: &[u8], signature_key: &[u8]) -> Vec<u8> {
+    let mut input = FINGERPRINT_VERSION.to_be_bytes().to_vec();
+    for field in [credential, signature_key] {
+        input.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        input.extend_from_slice(field);
+    }
+
+    let mut digest = Sha512::digest(&input).to_vec();
+    for _ in 1..ITERATIONS {
+        let mut hasher = Sha512::new();
+        hasher.update(&digest);
+        hasher.update(signature_key);
+        digest = hasher.finalize().to_vec();
+    }
+    digest.truncate(FINGERPRINT_LEN);
+    digest
+}
+
+/// Verification code over every member of a group. It changes whenever a
+/// member joins, leaves or changes keys, but not with the epoch.
+pub fn group_code(group_id: &[u8], fingerprints: &[Vec<u8>]) -> String {
+    let mut fingerprints = fingerprints.to_vec();
+    fingerprints.sort();
+
+    let mut hasher = Sha512::new();
+    hasher.update(GROUP_CODE_LABEL);
+    hasher.update(FINGERPRINT_VERSION.to_be_bytes());
+    hasher.update((group_id.len() as u32).to_be_bytes());
+    hasher.update(group_id);
+    for fingerprint in &fingerprints {
+        hasher.update(fingerprint);
+    }
+    numeric(&hasher.finalize())
+}
+
+/// Render a fingerprint as six groups of five digits, e.g.
+/// `12345 67890 13579 24680 11223 34455`
+pub fn numeric(fingerprint: &[u8]) -> String {
+    fingerprint
+        .chunks(5)
+        .take(CHUNKS)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |value, byte| (value << 8) | u64::from(*byte));
+            format!("{:0width$}", value % 100_000, width = DIGITS_PER_CHUNK)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The payload to encode in a QR code for scanning: version then
+/// fingerprint bytes
+pub fn qr_payload(fingerprint: &[u8]) -> Vec<u8> {
+    let mut payload = FINGERPRINT_VERSION.to_be_bytes().to_vec();
+    payload.extend_from_slice(fingerprint);
+    payload
+}
@@ -0,0 +1,192 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// Separates the user ID from the device ID in the text form of an identity
+pub const DEVICE_SEPARATOR: char = '#';
+
+/// First byte of the canonical encoding. Identities written before it was
+/// introduced are bare UTF-8 `<user_id>#<device_id>` strings, which never
+/// start with a zero byte.
+const ENCODING_TAG: u8 = 0;
+
+/// Version of the canonical encoding
+const ENCODING_VERSION: u8 = 1;
+
+/// Longest display name accepted, in characters
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+/// The identity a client presents in its credential: a user, optionally one
+/// of the user's devices, and an optional display name.
+///
+/// User and device IDs are normalized before use: NFKC, lower case, no
+/// whitespace, control or invisible formatting characters, and no `#`. Two
+/// IDs that normalize the same name the same member. The display name is
+/// only NFC-normalized and trimmed, and is never used for matching.
+/// Cross-script look-alikes (e.g. Latin `a` and Cyrillic `а`) stay distinct.
+///
+/// In credentials the identity is encoded canonically as
+/// `0x00 | version | user | device | display name`, each field a u16
+/// length-prefixed UTF-8 string with absent fields empty.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    pub user_id: String,
+    pub device_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+impl Identity {
+    pub fn new(user_id: &str, device_id: Option<&str>, display_name: Option<&str>) -> Result<Self> {
+        let user_id = normalize_id("User ID", user_id)?;
+        let device_id = device_id.map(|id| normalize_id("Device ID", id)).transpose()?;
+        let display_name = display_name.map(normalize_display_name).transpose()?.flatten();
+
+        Ok(Self {
+            user_id,
+            device_id,
+            display_name,
+        })
+    }
+
+    /// Parse the identity bytes of a credential, in the canonical encoding
+    /// or the older `<user_id>#<device_id>` text form
+    pub fn parse(identity: &[u8]) -> Result<Self> {
+        if identity.first() == Some(&ENCODING_TAG) {
+            return Self::decode(identity);
+        }
+
+        std::str::from_utf8(identity)
+            .map_err(|_| Error::InvalidIdentity("Identity is not valid UTF-8".to_string()))?
+            .parse()
+    }
+
+    /// The canonical encoding carried in credentials
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = vec![ENCODING_TAG, ENCODING_VERSION];
+        for field in [
+            Some(&self.user_id),
+            self.device_id.as_ref(),
+            self.display_name.as_ref(),
+        ] {
+            let field = field.map_or(&[][..], |field| field.as_bytes());
+            encoded.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(field);
+        }
+        encoded
+    }
+
+    /// Whether both name the same user and device, ignoring display names
+    pub fn same_member(&self, other: &Identity) -> bool {
+        self.user_id == other.user_id && self.device_id == other.device_id
+    }
+
+    fn decode(encoded: &[u8]) -> Result<Self> {
+        let malformed = || Error::InvalidIdentity("Malformed identity encoding".to_string());
+        if encoded.get(1) != Some(&ENCODING_VERSION) {
+            return Err(Error::InvalidIdentity("Unknown identity encoding version".to_string()));
+        }
+
+        let mut rest = &encoded[2..];
+        let mut fields = Vec::with_capacity(3);
+        for _ in 0..3 {
+            if rest.len() < 2 {
+                return Err(malformed());
+            }
+            let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            let field = rest.get(2..2 + len).ok_or_else(malformed)?;
+            let field = std::str::from_utf8(field).map_err(|_| malformed())?;
+            fields.push((!field.is_empty()).then_some(field));
+            rest = &rest[2 + len..];
+        }
+        if !rest.is_empty() {
+            return Err(malformed());
+        }
+
+        let identity = Self::new(fields[0].unwrap_or_default(), fields[1], fields[2])?;
+        // Only the normalized form is valid, so equal identities have equal bytes
+        if identity.encode() != encoded {
+            return Err(Error::InvalidIdentity("Identity encoding is not canonical".to_string()));
+        }
+        Ok(identity)
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    /// Parse the text form `<user_id>` or `<user_id>#<device_id>`
+    fn from_str(identity: &str) -> Result<Self> {
+        match identity.split_once(DEVICE_SEPARATOR) {
+            Some((user_id, device_id)) => Self::new(user_id, Some(device_id), None),
+            None => Self::new(identity, None, None),
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    /// The text form, without the display name
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.device_id {
+            Some(device_id) => write!(f, "{}{}{}", self.user_id, DEVICE_SEPARATOR, device_id),
+            None => f.write_str(&self.user_id),
+        }
+    }
+}
+
+fn normalize_id(kind: &str, id: &str) -> Result<String> {
+    let normalized: String = id.nfkc().collect::<String>().to_lowercase();
+    if normalized.is_empty() {
+        return Err(Error::InvalidIdentity(format!("{} must not be empty", kind)));
+    }
+    if normalized
+        .chars()
+        .any(|c| c == DEVICE_SEPARATOR || c.is_whitespace() || is_invisible(c))
+    {
+        return Err(Error::InvalidIdentity(format!(
+            "{} must not contain whitespace, invisible characters or `{}`",
+            kind, DEVICE_SEPARATOR
+        )));
+    }
+    if normalized.len() > u16::MAX as usize {
+        return Err(Error::InvalidIdentity(format!("{} is too long", kind)));
+    }
+    Ok(normalized)
+}
+
+fn normalize_display_name(name: &str) -> Result<Option<String>> {
+    let normalized: String = name.nfc().collect::<String>().trim().to_string();
+    if normalized.chars().any(is_invisible) {
+        return Err(Error::InvalidIdentity(
+            "Display name must not contain control or invisible characters".to_string(),
+        ));
+    }
+    if normalized.chars().count() > MAX_DISPLAY_NAME_LEN {
+        return Err(Error::InvalidIdentity(format!(
+            "Display name must be at most {} characters",
+            MAX_DISPLAY_NAME_LEN
+        )));
+    }
+    Ok((!normalized.is_empty()).then_some(normalized))
+}
+
+/// Control characters and the zero-width and bidi formatting characters
+/// that make two IDs look identical
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{00AD}'
+                | '\u{034F}'
+                | '\u{061C}'
+                | '\u{180E}'
+                | '\u{200B}'..='\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2060}'..='\u{206F}'
+                | '\u{FE00}'..='\u{FE0F}'
+                | '\u{FEFF}'
+        )
+}
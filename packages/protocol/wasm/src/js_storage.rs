@@ -0,0 +1,111 @@
+use crate::error::{Error, Result};
+use crate::storage::{StorageBackend, StorageRecord};
+use js_sys::{Array, Function, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// A storage backend that forwards every operation to a JS object.
+///
+/// The object must provide synchronous `get(namespace, key)`,
+/// `put(namespace, key, value)` and `delete(namespace, key)` methods, where
+/// keys and values are `Uint8Array`s and `get` returns `null` or `undefined`
+/// for missing records. Snapshots additionally require `entries()`, returning
+/// an array of `[namespace, key, value]` triples, and `clear()`.
+pub struct JsStorageBackend {
+    store: JsValue,
+}
+
+impl JsStorageBackend {
+    pub fn new(store: JsValue) -> Result<Self> {
+        if !store.is_object() {
+            return Err(Error::StorageError(
+                "Storage must be an object with get, put and delete methods".to_string(),
+            ));
+        }
+
+        let backend = Self { store };
+        for name in ["get", "put", "delete"] {
+            backend.method(name)?;
+        }
+
+        Ok(backend)
+    }
+
+    /// Look up a method on the JS store object
+    fn method(&self, name: &str) -> Result<Function> {
+        Reflect::get(&self.store, &JsValue::from_str(name))
+            .map_err(js_error)?
+            .dyn_into::<Function>()
+            .map_err(|_| Error::StorageError(format!("Storage is missing a `{}` method", name)))
+    }
+}
+
+impl StorageBackend for JsStorageBackend {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let value = self
+            .method("get")?
+            .call2(&self.store, &JsValue::from_str(namespace), &Uint8Array::from(key))
+            .map_err(js_error)?;
+
+        if value.is_null() || value.is_undefined() {
+            return Ok(None);
+        }
+
+        let value = value
+            .dyn_into::<Uint8Array>()
+            .map_err(|_| Error::StorageError("Storage `get` must return a Uint8Array".to_string()))?;
+        Ok(Some(value.to_vec()))
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.method("put")?
+            .call3(
+                &self.store,
+                &JsValue::from_str(namespace),
+                &Uint8Array::from(key),
+                &Uint8Array::from(value),
+            )
+            .map_err(js_error)?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<()> {
+        self.method("delete")?
+            .call2(&self.store, &JsValue::from_str(namespace), &Uint8Array::from(key))
+            .map_err(js_error)?;
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<StorageRecord>> {
+        let entries = self
+            .method("entries")?
+            .call0(&self.store)
+            .map_err(js_error)?;
+
+        Array::from(&entries)
+            .iter()
+            .map(|entry| {
+                let entry = Array::from(&entry);
+                let namespace = entry.get(0).as_string().ok_or_else(|| {
+                    Error::StorageError("Storage entry namespace must be a string".to_string())
+                })?;
+                let key = Uint8Array::new(&entry.get(1)).to_vec();
+                let value = Uint8Array::new(&entry.get(2)).to_vec();
+                Ok((namespace, key, value))
+            })
+            .collect()
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.method("clear")?.call0(&self.store).map_err(js_error)?;
+        Ok(())
+    }
+}
+
+fn js_error(error: JsValue) -> Error {
+    Error::StorageError(
+        error
+            .as_string()
+            .unwrap_or_else(|| format!("{:?}", error)),
+    )
+}
@@ -0,0 +1,86 @@
+use crate::credentials::{credential_identity, credential_type_name, CredentialValidator};
+use crate::identity::Identity;
+use js_sys::{Function, Object, Reflect, Uint8Array};
+use openmls::prelude::{Credential, TlsSerializeTrait};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// A credential validator implemented by a JS function.
+///
+/// The function is called with `{ groupId, identity, userId, deviceId,
+/// displayName, credentialType, credential, signatureKey }`, where
+/// `credential` is the TLS-serialized credential and the other binary fields
+/// are `Uint8Array`s. `userId`, `deviceId` and `displayName` are the
+/// normalized identity, or `undefined` if it cannot be parsed. Returning
+/// `false` or a string, or throwing, vetoes the member; the string or the
+/// thrown message is reported as the reason.
+pub struct JsCredentialValidator {
+    callback: Function,
+}
+
+impl JsCredentialValidator {
+    pub fn new(callback: Function) -> Self {
+        Self { callback }
+    }
+}
+
+impl CredentialValidator for JsCredentialValidator {
+    fn validate(
+        &self,
+        group_id: &[u8],
+        credential: &Credential,
+        signature_key: &[u8],
+    ) -> std::result::Result<(), String> {
+        let member = member_to_js(group_id, credential, signature_key)?;
+        let verdict = self
+            .callback
+            .call1(&JsValue::NULL, &member)
+            .map_err(js_reason)?;
+
+        if let Some(reason) = verdict.as_string() {
+            return Err(reason);
+        }
+        if verdict.as_bool() == Some(false) {
+            return Err("Vetoed by credential validator".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn member_to_js(
+    group_id: &[u8],
+    credential: &Credential,
+    signature_key: &[u8],
+) -> std::result::Result<Object, String> {
+    let identity = credential_identity(credential).map_err(|e| e.to_string())?;
+    let parsed = Identity::parse(&identity).ok();
+    let field = |value: Option<&String>| value.map_or(JsValue::UNDEFINED, |v| JsValue::from_str(v));
+    let serialized = credential
+        .tls_serialize_detached()
+        .map_err(|e| e.to_string())?;
+
+    let member = Object::new();
+    for (name, value) in [
+        ("groupId", Uint8Array::from(group_id).into()),
+        ("identity", Uint8Array::from(identity.as_slice()).into()),
+        ("userId", field(parsed.as_ref().map(|p| &p.user_id))),
+        ("deviceId", field(parsed.as_ref().and_then(|p| p.device_id.as_ref()))),
+        ("displayName", field(parsed.as_ref().and_then(|p| p.display_name.as_ref()))),
+        ("credentialType", JsValue::from_str(credential_type_name(credential))),
+        ("credential", Uint8Array::from(serialized.as_slice()).into()),
+        ("signatureKey", Uint8Array::from(signature_key).into()),
+    ] {
+        Reflect::set(&member, &JsValue::from_str(name), &value)
+            .map_err(|_| format!("Failed to set `{}`", name))?;
+    }
+    Ok(member)
+}
+
+fn js_reason(error: JsValue) -> String {
+    if let Some(error) = error.dyn_ref::<js_sys::Error>() {
+        return String::from(error.message());
+    }
+    error
+        .as_string()
+        .unwrap_or_else(|| format!("{:?}", error))
+}
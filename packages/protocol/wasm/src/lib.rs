@@ -1,8 +1,32 @@
 mod mls_client;
 mod types;
 mod error;
+mod config;
+mod provider;
+mod capabilities;
+mod clock;
+mod credentials;
+mod identity;
+mod fingerprint;
+mod attestation;
+mod js_validator;
 mod utils;
 mod storage;
+mod migrations;
+mod js_storage;
+mod encrypted_storage;
+mod backup;
+mod cached_storage;
+
+pub use attestation::CredentialIssuer;
+pub use clock::Clock;
+pub use credentials::CredentialValidator;
+pub use identity::Identity;
+pub use mls_client::{MLSClient, MLSGroup};
+pub use types::{
+    BatchCommit, BatchItem, BatchStatus, GroupCapabilities, GroupCommit, KeyPackageCapabilities, KeyPackageInfo, MLSCiphertext, MLSCommit, MemberFingerprint,
+    UserDevices,
+};
 
 use wasm_bindgen::prelude::*;
 
@@ -0,0 +1,82 @@
+use crate::error::{Error, Result};
+use openmls_traits::storage::CURRENT_VERSION;
+
+/// Version written in the header of every stored record. It follows the
+/// openmls storage version, so bumping openmls to a release with a new
+/// `CURRENT_VERSION` requires registering a migration for the old layout.
+pub const RECORD_VERSION: u16 = CURRENT_VERSION;
+
+/// First byte of every record header, followed by the big-endian u16
+/// version. JSON never starts with it, so headed records are told apart from
+/// bare ones by this tag rather than by the value of the version.
+const RECORD_TAG: u8 = 0xFF;
+
+/// Length of the tag and version header
+const HEADER_LEN: usize = 3;
+
+/// Version assigned to records written before headers were introduced,
+/// which are bare JSON
+const UNVERSIONED: u16 = 0;
+
+/// A step that upgrades the payload of a record from version `from` to
+/// version `from + 1`
+pub struct Migration {
+    pub from: u16,
+    pub upgrade: fn(namespace: &str, payload: Vec<u8>) -> Result<Vec<u8>>,
+}
+
+/// Every known migration, applied in order of `from` on load
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: UNVERSIONED,
+    upgrade: unversioned_to_v1,
+}];
+
+/// The JSON layout did not change when headers were introduced
+fn unversioned_to_v1(_namespace: &str, payload: Vec<u8>) -> Result<Vec<u8>> {
+    Ok(payload)
+}
+
+/// Prefix a payload with the tag and current version header
+pub fn encode_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.push(RECORD_TAG);
+    record.extend_from_slice(&RECORD_VERSION.to_be_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Strip the header of a stored record, running any migrations needed to
+/// bring it to `RECORD_VERSION`. Returns the payload and whether it was
+/// migrated, in which case the caller should write it back.
+pub fn decode_record(namespace: &str, record: &[u8]) -> Result<(Vec<u8>, bool)> {
+    let (mut version, payload) = match record.first() {
+        Some(&RECORD_TAG) if record.len() >= HEADER_LEN => (
+            u16::from_be_bytes([record[1], record[2]]),
+            &record[HEADER_LEN..],
+        ),
+        _ => (UNVERSIONED, record),
+    };
+
+    if version > RECORD_VERSION {
+        return Err(Error::UnsupportedStorageVersion {
+            namespace: namespace.to_string(),
+            version,
+        });
+    }
+
+    let migrated = version < RECORD_VERSION;
+    let mut payload = payload.to_vec();
+    while version < RECORD_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or_else(|| Error::UnsupportedStorageVersion {
+                namespace: namespace.to_string(),
+                version,
+            })?;
+        payload = (migration.upgrade)(namespace, payload)?;
+        version += 1;
+    }
+
+    Ok((payload, migrated))
+}
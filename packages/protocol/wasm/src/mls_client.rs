@@ -1,20 +1,79 @@
+use crate::attestation::{Attestation, ATTESTED_CREDENTIAL_TYPE};
+use crate::backup::{open_backup, seal_backup, IdentityBackup};
+use crate::capabilities::{
+    check_features, features_in, required_features_extensions, ClientCapabilities,
+    FEATURES_EXTENSION_TYPE, REQUIRED_FEATURES_EXTENSION_TYPE,
+};
+use crate::cached_storage::{dirty_records_to_js, records_from_js, CachedBackend};
+use crate::encrypted_storage::{EncryptedBackend, StorageSecret};
+use crate::clock::{check_lifetime, Clock, JsClock, DEFAULT_KEY_PACKAGE_LIFETIME};
+use crate::config::{ciphersuite_name, CryptoSettings, MLSConfig};
+use crate::credentials::{
+    certified_key, credential_identity, credential_type_name, CredentialPolicy, CredentialValidator,
+};
 use crate::error::{Error, Result};
-use crate::storage::MLSStorage;
+use crate::fingerprint::{group_code, member_fingerprint, numeric, qr_payload};
+use crate::identity::Identity;
+use crate::js_storage::JsStorageBackend;
+use crate::js_validator::JsCredentialValidator;
+use crate::provider::{is_hybrid, CryptoProvider, MlsProvider};
+use crate::storage::{MLSStorage, StorageRecord};
 use crate::types::*;
 use openmls::prelude::*;
 use openmls_basic_credential::SignatureKeyPair;
-use openmls_rust_crypto::OpenMlsRustCrypto;
+use openmls_traits::crypto::OpenMlsCrypto;
+use openmls_traits::signatures::Signer;
+use openmls_traits::storage::StorageProvider;
+use serde::{Deserialize, Serialize};
 use serde_wasm_bindgen::to_value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
 
 /// The main MLS client that manages groups and cryptographic operations
 #[wasm_bindgen]
 pub struct MLSClient {
     identity: Vec<u8>,
-    crypto_provider: OpenMlsRustCrypto,
+    crypto_provider: CryptoProvider,
     storage: MLSStorage,
     credential: Credential,
+    /// The active signature key, shared with every group handed out so a
+    /// rotation takes effect for all of them
+    signature_keys: Rc<RefCell<SignatureKeyPair>>,
+    settings: CryptoSettings,
+    cache: Option<Rc<CachedBackend>>,
+    encrypted_backend: Option<Rc<EncryptedBackend>>,
+    policy: Rc<RefCell<CredentialPolicy>>,
+    /// Seconds that exported key packages stay valid
+    key_package_lifetime: u64,
+    /// Features and app-defined extensions advertised in new leaves
+    capabilities: ClientCapabilities,
+    /// Live groups handed out to JS, refreshed when the client changes them
+    /// in storage behind their back
+    live_groups: RefCell<Vec<Weak<RefCell<MlsGroup>>>>,
+}
+
+/// Version of the serialized `ClientState` layout
+const CLIENT_STATE_VERSION: u16 = 1;
+
+/// Persisted long-term identity of an `MLSClient`
+#[derive(Serialize, Deserialize)]
+struct ClientState {
+    version: u16,
+    identity: Vec<u8>,
+    credential: Vec<u8>,
     signature_keys: SignatureKeyPair,
+    /// Absent in state exported before ciphersuites were configurable
+    #[serde(default)]
+    ciphersuite: Option<String>,
+    /// Absent in state exported before capabilities were configurable
+    #[serde(default)]
+    capabilities: ClientCapabilities,
+    /// Absent in state exported before key package lifetimes were configurable
+    #[serde(default)]
+    key_package_lifetime: Option<u64>,
 }
 
 #[wasm_bindgen]
@@ -22,60 +81,445 @@ impl MLSClient {
     /// Initialize a new MLS client with the given identity
     #[wasm_bindgen(js_name = initialize)]
     pub fn new(identity: String) -> Result<MLSClient> {
-        let crypto_provider = OpenMlsRustCrypto::default();
-        let storage = MLSStorage::new();
+        let storage = ClientStorage::cached(Vec::new(), &tenant_for(&identity, None)?)?;
+        Self::generate(identity.parse()?, storage, CryptoSettings::default())
+    }
+    
+    /// Initialize a new MLS client with a configuration object selecting the
+    /// ciphersuite, e.g. `{ ciphersuite: "MLS_128_DHKEMP256_AES128GCM_SHA256_P256" }`
+    #[wasm_bindgen(js_name = initializeWithConfig)]
+    pub fn with_config(identity: String, config: JsValue) -> Result<MLSClient> {
+        let settings = CryptoSettings::from_js(&config)?;
+        let storage = ClientStorage::cached(Vec::new(), &tenant_for(&identity, None)?)?;
+        Self::generate(identity.parse()?, storage, settings)
+    }
+    
+    /// Initialize a new MLS client for one device of a user. `restore`
+    /// expects its identity as `<userId>#<deviceId>`.
+    #[wasm_bindgen(js_name = initializeDevice)]
+    pub fn for_device(user_id: &str, device_id: &str, config: JsValue) -> Result<MLSClient> {
+        let identity = Identity::new(user_id, Some(device_id), None)?;
+        let settings = CryptoSettings::from_js(&config)?;
+        let storage = ClientStorage::cached(Vec::new(), &identity.to_string())?;
+        Self::generate(identity, storage, settings)
+    }
+    
+    /// Initialize a new MLS client from a structured identity object
+    /// `{ userId, deviceId?, displayName? }`
+    #[wasm_bindgen(js_name = initializeWithIdentity)]
+    pub fn with_identity(identity: JsValue, config: JsValue) -> Result<MLSClient> {
+        let identity: Identity = serde_wasm_bindgen::from_value(identity)
+            .map_err(|e| Error::SerializationError(e.to_string()))?;
+        let identity = Identity::new(
+            &identity.user_id,
+            identity.device_id.as_deref(),
+            identity.display_name.as_deref(),
+        )?;
         
-        // Create credential from identity
-        let identity_bytes = identity.as_bytes().to_vec();
-        let credential = Credential::new_basic(identity_bytes.clone());
+        let settings = CryptoSettings::from_js(&config)?;
+        let storage = ClientStorage::cached(Vec::new(), &identity.to_string())?;
+        Self::generate(identity, storage, settings)
+    }
+    
+    /// Initialize a new MLS client whose records are kept in a JS store
+    /// exposing `get`, `put` and `delete`. Records are scoped to `tenant`,
+    /// which defaults to the normalized identity.
+    #[wasm_bindgen(js_name = initializeWithStorage)]
+    pub fn with_storage(
+        identity: String,
+        store: JsValue,
+        tenant: Option<String>,
+        config: JsValue,
+    ) -> Result<MLSClient> {
+        let settings = CryptoSettings::from_js(&config)?;
+        let storage = ClientStorage::js(store, &tenant_for(&identity, tenant)?)?;
+        Self::generate(identity.parse()?, storage, settings)
+    }
+    
+    /// Initialize a new MLS client whose credential is an X.509 chain of DER
+    /// certificates, leaf first. The identity is the leaf's common name and
+    /// `private_key` is the raw private key matching the certified key.
+    #[wasm_bindgen(js_name = initializeWithCertificate)]
+    pub fn with_certificate(
+        chain: js_sys::Array,
+        private_key: &[u8],
+        config: JsValue,
+    ) -> Result<MLSClient> {
+        let settings = CryptoSettings::from_js(&config)?;
+        let chain: Vec<Vec<u8>> = chain
+            .iter()
+            .map(|certificate| js_sys::Uint8Array::new(&certificate).to_vec())
+            .collect();
+        let leaf = chain
+            .first()
+            .ok_or_else(|| Error::InvalidCredential("Empty certificate chain".to_string()))?;
         
-        // Generate signature key pair
-        let signature_keys = SignatureKeyPair::new(SignatureScheme::ED25519)
-            .map_err(|e| Error::CryptoError(e.to_string()))?;
+        let (signature_scheme, public_key) = certified_key(leaf)?;
+        if signature_scheme != settings.signature_scheme {
+            return Err(Error::InvalidCredential(format!(
+                "Certificate key cannot be used with {}",
+                ciphersuite_name(settings.ciphersuite)
+            )));
+        }
         
-        // Store the signature key pair
-        signature_keys
-            .store(&storage)
-            .map_err(|e| Error::StorageError(e.to_string()))?;
+        // Make sure the private key is the one the leaf certifies
+        let signature_keys = SignatureKeyPair::from_raw(signature_scheme, private_key.to_vec(), public_key);
+        let probe = b"opencall-mls certificate key check";
+        let signature = signature_keys
+            .sign(probe)
+            .map_err(|e| Error::CryptoError(format!("{:?}", e)))?;
+        CryptoProvider::default()
+            .verify_signature(signature_scheme, probe, signature_keys.public(), &signature)
+            .map_err(|_| {
+                Error::InvalidCredential("Private key does not match the leaf certificate".to_string())
+            })?;
         
-        Ok(MLSClient {
-            identity: identity_bytes,
-            crypto_provider,
-            storage,
+        let credential = Credential::new_x509(chain);
+        let identity = credential_identity(&credential)?;
+        let storage = ClientStorage::cached(Vec::new(), &Identity::parse(&identity)?.to_string())?;
+        Self::from_parts(identity, credential, signature_keys, storage, settings)
+    }
+    
+    /// Restore a client from state previously produced by `exportState`
+    #[wasm_bindgen(js_name = restore)]
+    pub fn restore(identity: String, state_bytes: &[u8]) -> Result<MLSClient> {
+        let storage = ClientStorage::cached(Vec::new(), &tenant_for(&identity, None)?)?;
+        Self::restore_into(identity, state_bytes, storage)
+    }
+    
+    /// Restore a client on top of a JS store that already holds its groups
+    #[wasm_bindgen(js_name = restoreWithStorage)]
+    pub fn restore_with_storage(
+        identity: String,
+        state_bytes: &[u8],
+        store: JsValue,
+        tenant: Option<String>,
+    ) -> Result<MLSClient> {
+        let storage = ClientStorage::js(store, &tenant_for(&identity, tenant)?)?;
+        Self::restore_into(identity, state_bytes, storage)
+    }
+    
+    /// Restore a client and preload its write-behind cache with records
+    /// previously returned by `flush`. Records are scoped to `tenant`, which
+    /// defaults to the normalized identity.
+    #[wasm_bindgen(js_name = hydrate)]
+    pub fn hydrate(
+        identity: String,
+        state_bytes: &[u8],
+        records: JsValue,
+        tenant: Option<String>,
+    ) -> Result<MLSClient> {
+        let storage = ClientStorage::cached(records_from_js(&records)?, &tenant_for(&identity, tenant)?)?;
+        Self::restore_into(identity, state_bytes, storage)
+    }
+    
+    /// Initialize a new MLS client whose records are encrypted at rest.
+    /// `store` is a JS store, or `null` to use the write-behind cache, and
+    /// `secret` is a passphrase string or a 32-byte `Uint8Array`.
+    #[wasm_bindgen(js_name = initializeEncrypted)]
+    pub fn with_encrypted_storage(
+        identity: String,
+        store: JsValue,
+        secret: JsValue,
+        tenant: Option<String>,
+        config: JsValue,
+    ) -> Result<MLSClient> {
+        let settings = CryptoSettings::from_js(&config)?;
+        let tenant = tenant_for(&identity, tenant)?;
+        let storage = ClientStorage::encrypted(store, Vec::new(), &secret, &tenant)?;
+        Self::generate(identity.parse()?, storage, settings)
+    }
+    
+    /// Restore a client on top of an encrypted JS store
+    #[wasm_bindgen(js_name = restoreEncrypted)]
+    pub fn restore_with_encrypted_storage(
+        identity: String,
+        state_bytes: &[u8],
+        store: JsValue,
+        secret: JsValue,
+        tenant: Option<String>,
+    ) -> Result<MLSClient> {
+        let tenant = tenant_for(&identity, tenant)?;
+        let storage = ClientStorage::encrypted(store, Vec::new(), &secret, &tenant)?;
+        Self::restore_into(identity, state_bytes, storage)
+    }
+    
+    /// Restore a client whose encrypted write-behind cache is preloaded with
+    /// records previously returned by `flush`, scoped to `tenant` as in
+    /// `hydrate`
+    #[wasm_bindgen(js_name = hydrateEncrypted)]
+    pub fn hydrate_encrypted(
+        identity: String,
+        state_bytes: &[u8],
+        records: JsValue,
+        secret: JsValue,
+        tenant: Option<String>,
+    ) -> Result<MLSClient> {
+        let records = records_from_js(&records)?;
+        let tenant = tenant_for(&identity, tenant)?;
+        let storage = ClientStorage::encrypted(JsValue::NULL, records, &secret, &tenant)?;
+        Self::restore_into(identity, state_bytes, storage)
+    }
+    
+    /// Recover a client from a bundle produced by `exportBackup`, sealed
+    /// under `secret` (a passphrase string or a 32-byte `Uint8Array`)
+    #[wasm_bindgen(js_name = importBackup)]
+    pub fn import_backup(identity: String, bundle: &[u8], secret: JsValue) -> Result<MLSClient> {
+        let backup = open_backup(bundle, &StorageSecret::from_js(&secret)?)?;
+        
+        let storage = ClientStorage::cached(Vec::new(), &tenant_for(&identity, None)?)?;
+        let client = Self::restore_into(identity, &backup.state, storage)?;
+        client
+            .storage
+            .import_key_package_records(&backup.key_packages, &backup.last_resort)?;
+        Ok(client)
+    }
+    
+    /// Collect the records changed since the last flush from the write-behind
+    /// cache. If `persist` is given it is called with the records and awaited;
+    /// the records stay dirty if it throws or rejects.
+    #[wasm_bindgen(js_name = flush)]
+    pub fn flush(&self, persist: Option<js_sys::Function>) -> Result<js_sys::Promise> {
+        let cache = self
+            .cache
+            .clone()
+            .ok_or_else(|| Error::InvalidState("Client has no write-behind cache".to_string()))?;
+        let records = cache.take_dirty()?;
+        let js_records = dirty_records_to_js(&records)?;
+        
+        Ok(future_to_promise(async move {
+            if let Some(persist) = persist {
+                let persisted = match persist.call1(&JsValue::NULL, &js_records) {
+                    Ok(result) => JsFuture::from(js_sys::Promise::resolve(&result)).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = persisted {
+                    cache.restore_dirty(&records);
+                    return Err(e);
+                }
+            }
+            Ok(js_records.into())
+        }))
+    }
+    
+    /// Re-encrypt the client's storage under a new passphrase or key
+    #[wasm_bindgen(js_name = rekeyStorage)]
+    pub fn rekey_storage(&self, secret: JsValue) -> Result<()> {
+        let backend = self
+            .encrypted_backend
+            .as_ref()
+            .ok_or_else(|| Error::InvalidState("Storage is not encrypted".to_string()))?;
+        backend.rekey(&StorageSecret::from_js(&secret)?)
+    }
+    
+    /// Export the long-term identity (credential and signature key pair)
+    /// and the client settings key packages are built from
+    #[wasm_bindgen(js_name = exportState)]
+    pub fn export_state(&self) -> Result<Vec<u8>> {
+        let credential = self
+            .credential
+            .tls_serialize_detached()
+            .map_err(|e| Error::CodecError(e.to_string()))?;
+        
+        let state = ClientState {
+            version: CLIENT_STATE_VERSION,
+            identity: self.identity.clone(),
             credential,
-            signature_keys,
-        })
+            signature_keys: self.signature_keys.borrow().clone(),
+            ciphersuite: Some(ciphersuite_name(self.settings.ciphersuite)),
+            capabilities: self.capabilities.clone(),
+            key_package_lifetime: Some(self.key_package_lifetime),
+        };
+        
+        serde_json::to_vec(&state).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Replace the client's signature key. Every active stored group gets an
+    /// update commit moving this client's leaf to the new key, and the old
+    /// key is removed from storage once all groups have moved over. Returns
+    /// a `GroupCommit` per updated group to send to the other members.
+    /// Groups this client was removed from and groups with a pending commit
+    /// are skipped, and the old key is kept while one of the latter still
+    /// uses it. If any other group cannot be updated nothing changes.
+    #[wasm_bindgen(js_name = rotateSignatureKey)]
+    pub fn rotate_signature_key(&mut self) -> Result<JsValue> {
+        if !matches!(self.credential, Credential::Basic(_)) {
+            return Err(Error::InvalidCredential(
+                "Certified credentials must be re-issued for a new signature key".to_string(),
+            ));
+        }
+        
+        let new_keys = SignatureKeyPair::new(self.settings.signature_scheme)
+            .map_err(|e| Error::CryptoError(e.to_string()))?;
+        
+        self.storage.begin_transaction()?;
+        let commits = match self.rotate_groups(&new_keys) {
+            Ok(commits) => {
+                self.storage.commit_transaction()?;
+                commits
+            }
+            Err(e) => {
+                self.storage.rollback_transaction();
+                return Err(e);
+            }
+        };
+        
+        *self.signature_keys.borrow_mut() = new_keys;
+        self.refresh_live_groups()?;
+        
+        to_value(&commits).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Export the long-term identity as a bundle sealed under `secret`, a
+    /// recovery passphrase or a 32-byte `Uint8Array` key. With
+    /// `include_key_packages`, unused key packages are bundled too so that
+    /// Welcomes addressed to them can still be accepted after recovery.
+    #[wasm_bindgen(js_name = exportBackup)]
+    pub fn export_backup(&self, secret: JsValue, include_key_packages: bool) -> Result<Vec<u8>> {
+        let (key_packages, last_resort) = if include_key_packages {
+            (
+                self.storage.unused_key_package_records()?,
+                self.storage.last_resort_key_package_keys()?,
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        
+        let backup = IdentityBackup {
+            state: self.export_state()?,
+            key_packages,
+            last_resort,
+        };
+        seal_backup(&backup, &StorageSecret::from_js(&secret)?)
+    }
+    
+    /// Get the public half of the client's signature key
+    #[wasm_bindgen(getter, js_name = signaturePublicKey)]
+    pub fn signature_public_key(&self) -> Vec<u8> {
+        self.signature_keys.borrow().public().to_vec()
+    }
+    
+    /// Get the client's identity as `{ userId, deviceId, displayName }`
+    #[wasm_bindgen(getter)]
+    pub fn identity(&self) -> Result<JsValue> {
+        serde_wasm_bindgen::to_value(&Identity::parse(&self.identity)?)
+            .map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Get the name of the ciphersuite this client uses by default
+    #[wasm_bindgen(getter)]
+    pub fn ciphersuite(&self) -> String {
+        ciphersuite_name(self.settings.ciphersuite)
+    }
+    
+    /// Trust a DER-encoded root certificate for member X.509 chains. Once
+    /// any anchor is set, members with basic credentials are rejected.
+    #[wasm_bindgen(js_name = addTrustAnchor)]
+    pub fn add_trust_anchor(&self, certificate: &[u8]) -> Result<()> {
+        self.policy.borrow_mut().trust_anchors.add(certificate)
+    }
+    
+    /// Remove every trust anchor, accepting basic credentials again
+    #[wasm_bindgen(js_name = clearTrustAnchors)]
+    pub fn clear_trust_anchors(&self) {
+        self.policy.borrow_mut().trust_anchors.clear();
+    }
+    
+    /// Replace the client's credential with an attestation issued by the
+    /// auth server for this identity and signature key. Groups created or
+    /// joined afterwards present the attested credential.
+    #[wasm_bindgen(js_name = setAttestation)]
+    pub fn set_attestation(&mut self, attestation: &[u8]) -> Result<()> {
+        let decoded = Attestation::decode(attestation)?;
+        if !Identity::parse(&decoded.identity)?.same_member(&Identity::parse(&self.identity)?) {
+            return Err(Error::InvalidCredential(
+                "Attestation was issued for a different identity".to_string(),
+            ));
+        }
+        if decoded.signature_key != self.signature_keys.borrow().public() {
+            return Err(Error::InvalidCredential(
+                "Attestation does not cover this client's signature key".to_string(),
+            ));
+        }
+        
+        self.credential = Credential::Other(ATTESTED_CREDENTIAL_TYPE, attestation.to_vec());
+        Ok(())
+    }
+    
+    /// Require members to present attestations signed by the auth server's
+    /// Ed25519 `public_key`, or stop requiring them with `null`
+    #[wasm_bindgen(js_name = setAttestationKey)]
+    pub fn set_attestation_key(&self, public_key: Option<Vec<u8>>) {
+        self.policy.borrow_mut().attestation_key = public_key;
+    }
+    
+    /// Install a JS function deciding whether new members are acceptable, or
+    /// remove it with `null`. See `JsCredentialValidator` for the contract.
+    #[wasm_bindgen(js_name = setCredentialValidator)]
+    pub fn set_js_credential_validator(&self, callback: Option<js_sys::Function>) {
+        self.policy.borrow_mut().validator = callback
+            .map(|callback| Box::new(JsCredentialValidator::new(callback)) as Box<dyn CredentialValidator>);
+    }
+    
+    /// Install a JS function returning the current time in milliseconds, like
+    /// `Date.now`, or go back to `Date.now` itself with `null`. It is used
+    /// for key package lifetimes, certificates and attestations. Key packages
+    /// must also be valid by the system clock, which openmls checks itself.
+    #[wasm_bindgen(js_name = setClock)]
+    pub fn set_js_clock(&self, callback: Option<js_sys::Function>) {
+        self.policy.borrow_mut().clock =
+            callback.map(|callback| Box::new(JsClock::new(callback)) as Box<dyn Clock>);
+    }
+    
+    /// Set how many seconds key packages exported from now on stay valid
+    #[wasm_bindgen(js_name = setKeyPackageLifetime)]
+    pub fn set_key_package_lifetime(&mut self, seconds: u32) -> Result<()> {
+        if seconds == 0 {
+            return Err(Error::InvalidState(
+                "Key package lifetime must be positive".to_string(),
+            ));
+        }
+        self.key_package_lifetime = u64::from(seconds);
+        Ok(())
+    }
+    
+    /// Advertise application features, e.g. `["sframe", "screen-share"]`,
+    /// in key packages exported and groups created from now on. Existing
+    /// leaves keep what they advertised.
+    #[wasm_bindgen(js_name = setCapabilities)]
+    pub fn set_capabilities(&mut self, features: Vec<String>) -> Result<()> {
+        self.capabilities.set_features(features)
+    }
+    
+    /// Carry an app-defined extension of `extension_type`, from the
+    /// private-use range, in the leaves of key packages exported and groups
+    /// created from now on. `null` data removes it.
+    #[wasm_bindgen(js_name = setLeafExtension)]
+    pub fn set_leaf_extension(&mut self, extension_type: u16, data: Option<Vec<u8>>) -> Result<()> {
+        self.capabilities.set_extension(extension_type, data)
     }
     
     /// Create a new MLS group
     #[wasm_bindgen(js_name = createGroup)]
     pub fn create_group(&self, group_id: Vec<u8>) -> Result<MLSGroup> {
-        let mls_group_config = MlsGroupCreateConfig::builder()
-            .crypto_config(CryptoConfig::default())
-            .build();
-        
-        let mut group = MlsGroup::new_with_group_id(
-            &self.crypto_provider,
-            &self.signature_keys,
-            &mls_group_config,
-            group_id.clone().into(),
-            CredentialWithKey {
-                credential: self.credential.clone(),
-                signature_key: self.signature_keys.public().into(),
-            },
-        )
-        .map_err(|e| Error::OpenMlsError(e.to_string()))?;
-        
-        // Store the group
-        group
-            .save(&self.storage)
-            .map_err(|e| Error::StorageError(e.to_string()))?;
+        self.create_group_with_settings(group_id, self.settings, Vec::new())
+    }
+    
+    /// Create a new MLS group with its own configuration object. The
+    /// ciphersuite must use the same signature scheme as the client, and
+    /// `requiredCapabilities` lists features every member must advertise.
+    #[wasm_bindgen(js_name = createGroupWithConfig)]
+    pub fn create_group_with_config(&self, group_id: Vec<u8>, config: JsValue) -> Result<MLSGroup> {
+        let config = MLSConfig::from_js(&config)?;
+        let settings = CryptoSettings::from_config(&config)?;
+        if settings.signature_scheme != self.settings.signature_scheme {
+            return Err(Error::CiphersuiteMismatch {
+                expected: ciphersuite_name(self.settings.ciphersuite),
+                found: ciphersuite_name(settings.ciphersuite),
+            });
+        }
         
-        Ok(MLSGroup {
-            group_id,
-            crypto_provider: self.crypto_provider.clone(),
-            storage: self.storage.clone(),
-        })
+        self.create_group_with_settings(group_id, settings, config.required_capabilities)
     }
     
     /// Join an existing group using a welcome message
@@ -89,69 +533,581 @@ impl MLSClient {
             _ => return Err(Error::InvalidMessageType("Expected welcome message".to_string())),
         };
         
-        let mls_group_config = MlsGroupJoinConfig::builder()
-            .crypto_config(CryptoConfig::default())
-            .build();
+        // Remember which of our key packages the welcome is addressed to,
+        // before joining deletes them
+        let key_package_refs: Vec<KeyPackageRef> = welcome
+            .secrets()
+            .iter()
+            .map(|secrets| secrets.new_member())
+            .collect();
+        let key_package_refs = self.storage.consumable_key_packages(&key_package_refs)?;
         
-        let mut group = MlsGroup::new_from_welcome(
-            &self.crypto_provider,
-            &mls_group_config,
-            welcome,
-            Some(&self.storage),
-        )?;
+        // Our leaf must be signed with the group's signature scheme
+        if welcome.ciphersuite().signature_algorithm() != self.settings.signature_scheme {
+            return Err(Error::CiphersuiteMismatch {
+                expected: ciphersuite_name(self.settings.ciphersuite),
+                found: ciphersuite_name(welcome.ciphersuite()),
+            });
+        }
         
-        // Get the group ID
-        let group_id = group.group_id().as_slice().to_vec();
+        let crypto_provider = supported_provider(welcome.ciphersuite())?;
         
-        // Store the group
-        group
-            .save(&self.storage)
-            .map_err(|e| Error::StorageError(e.to_string()))?;
+        // Nothing from a rejected welcome may reach storage
+        self.storage.begin_transaction()?;
+        let joined = self.join_from_welcome(welcome, &crypto_provider, &key_package_refs);
+        match joined {
+            Ok(group) => {
+                self.storage.commit_transaction()?;
+                Ok(self.wrap_group(group, crypto_provider))
+            }
+            Err(e) => {
+                self.storage.rollback_transaction();
+                Err(e)
+            }
+        }
+    }
+    
+    /// Open a group that is already held in this client's storage
+    #[wasm_bindgen(js_name = loadGroup)]
+    pub fn load_group(&self, group_id: Vec<u8>) -> Result<MLSGroup> {
+        let group_id: GroupId = group_id.into();
+        let group = MlsGroup::load(&self.storage, &group_id)
+            .map_err(|e| Error::StorageError(e.to_string()))?
+            .ok_or_else(|| Error::InvalidState("Group not found in storage".to_string()))?;
+        
+        let crypto_provider = supported_provider(group.ciphersuite())?;
+        Ok(self.wrap_group(group, crypto_provider))
+    }
+    
+    /// Export a snapshot of all groups and key material held by this client
+    #[wasm_bindgen(js_name = exportStorage)]
+    pub fn export_storage(&self) -> Result<Vec<u8>> {
+        self.storage.export_snapshot()
+    }
+    
+    /// Replace this client's storage with a snapshot from `exportStorage`
+    #[wasm_bindgen(js_name = importStorage)]
+    pub fn import_storage(&self, snapshot: &[u8]) -> Result<()> {
+        self.storage.import_snapshot(snapshot)?;
+        
+        // The snapshot may predate this client's signature key, keep it usable
+        self.signature_keys
+            .borrow()
+            .store(&self.storage)
+            .map_err(|e| Error::StorageError(e.to_string()))
+    }
+    
+    /// List the storage namespaces (tenants) present in the shared backend
+    #[wasm_bindgen(js_name = listNamespaces)]
+    pub fn list_namespaces(&self) -> Result<Vec<String>> {
+        self.storage.tenants()
+    }
+    
+    /// Delete every record stored under `namespace`, returning how many
+    /// records were removed
+    #[wasm_bindgen(js_name = wipeNamespace)]
+    pub fn wipe_namespace(&self, namespace: &str) -> Result<u32> {
+        self.storage.wipe_tenant(namespace)
+    }
+    
+    /// Drop encryption keys of epochs more than `retain_epochs` behind the
+    /// newest epoch stored for each group, which is its current epoch unless
+    /// the group was left, and key packages already consumed by a Welcome
+    #[wasm_bindgen(js_name = compact)]
+    pub fn compact(&self, retain_epochs: u32) -> Result<CompactionReport> {
+        let stats = self.storage.compact(u64::from(retain_epochs))?;
         
-        Ok(MLSGroup {
-            group_id,
-            crypto_provider: self.crypto_provider.clone(),
-            storage: self.storage.clone(),
+        Ok(CompactionReport {
+            records_removed: stats.records_removed,
+            bytes_removed: stats.bytes_removed,
         })
     }
     
     /// Export a key package for this client
     #[wasm_bindgen(js_name = exportKeyPackage)]
     pub fn export_key_package(&self) -> Result<Vec<u8>> {
-        let key_package = KeyPackage::builder()
+        self.build_key_package(false)
+    }
+    
+    /// Generate `count` key packages in one call, e.g. to upload to a key
+    /// package directory. With `last_resort`, the last one is flagged as a
+    /// last-resort package: Welcomes never consume it, so it can be handed
+    /// out repeatedly once the others run out.
+    #[wasm_bindgen(js_name = generateKeyPackages)]
+    pub fn generate_key_packages(
+        &self,
+        count: u32,
+        last_resort: bool,
+    ) -> Result<Vec<js_sys::Uint8Array>> {
+        if count == 0 {
+            return Err(Error::InvalidState(
+                "At least one key package must be generated".to_string(),
+            ));
+        }
+        
+        // Build the whole batch before any of it reaches storage
+        self.storage.begin_transaction()?;
+        let batch: Result<Vec<Vec<u8>>> = (0..count)
+            .map(|i| self.build_key_package(last_resort && i + 1 == count))
+            .collect();
+        let batch = match batch {
+            Ok(batch) => {
+                self.storage.commit_transaction()?;
+                batch
+            }
+            Err(e) => {
+                self.storage.rollback_transaction();
+                return Err(e);
+            }
+        };
+        
+        Ok(batch
+            .iter()
+            .map(|key_package| js_sys::Uint8Array::from(key_package.as_slice()))
+            .collect())
+    }
+    
+    /// Verify the signature of someone else's key package and describe it,
+    /// e.g. to show a host who is waiting to be admitted. No group or
+    /// storage is touched. Fails if the key package is malformed or its
+    /// signature does not verify.
+    #[wasm_bindgen(js_name = inspectKeyPackage)]
+    pub fn inspect_key_package(&self, key_package_bytes: &[u8]) -> Result<JsValue> {
+        let key_package = KeyPackageIn::tls_deserialize_exact(key_package_bytes)
+            .map_err(|e| Error::CodecError(e.to_string()))?;
+        let crypto_provider = supported_provider(key_package.ciphersuite())?;
+        let key_package = key_package
+            .validate(&crypto_provider, ProtocolVersion::Mls10)
+            .map_err(|e| Error::InvalidCredential(format!("Key package does not verify: {:?}", e)))?;
+        
+        let leaf_node = key_package.leaf_node();
+        let credential = leaf_node.credential();
+        let signature_key = leaf_node.signature_key().as_slice();
+        let identity_bytes = credential_identity(credential)?;
+        let identity = Identity::parse(&identity_bytes).ok();
+        let capabilities = leaf_node.capabilities();
+        let lifetime = key_package.life_time();
+        
+        let policy = self.policy.borrow();
+        let trust_error = policy.check_trust(credential, signature_key).err().map(|e| e.to_string());
+        let info = KeyPackageInfo {
+            identity: identity
+                .as_ref()
+                .map_or_else(|| String::from_utf8_lossy(&identity_bytes).into_owned(), Identity::to_string),
+            user_id: identity.as_ref().map(|identity| identity.user_id.clone()),
+            device_id: identity.as_ref().and_then(|identity| identity.device_id.clone()),
+            display_name: identity.and_then(|identity| identity.display_name),
+            credential_type: credential_type_name(credential).to_string(),
+            signature_key: signature_key.to_vec(),
+            fingerprint: numeric(&credential_fingerprint(credential, signature_key)?),
+            ciphersuite: ciphersuite_name(key_package.ciphersuite()),
+            capabilities: KeyPackageCapabilities {
+                versions: capabilities.versions().iter().map(|&v| u16::from(v)).collect(),
+                ciphersuites: capabilities.ciphersuites().iter().map(|&c| u16::from(c)).collect(),
+                extensions: capabilities.extensions().iter().map(|&e| u16::from(e)).collect(),
+                proposals: capabilities.proposals().iter().map(|&p| u16::from(p)).collect(),
+                credentials: capabilities.credentials().iter().map(|&c| u16::from(c)).collect(),
+            },
+            extensions: extension_types(key_package.extensions()),
+            leaf_extensions: extension_types(leaf_node.extensions()),
+            last_resort: key_package.last_resort(),
+            not_before: lifetime.not_before(),
+            not_after: lifetime.not_after(),
+            lifetime_valid: check_lifetime(policy.now(), lifetime.not_before(), lifetime.not_after()).is_ok(),
+            verified: policy.authenticates() && trust_error.is_none(),
+            trust_error,
+        };
+        
+        to_value(&info).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Number of key packages that no Welcome has consumed yet, not counting
+    /// last-resort packages. Replenish the directory when this runs low.
+    #[wasm_bindgen(js_name = unusedKeyPackageCount)]
+    pub fn unused_key_package_count(&self) -> Result<u32> {
+        self.storage.unused_key_package_count()
+    }
+}
+
+impl MLSClient {
+    /// Install a native credential validator, replacing any previous one
+    pub fn set_credential_validator(&self, validator: impl CredentialValidator + 'static) {
+        self.policy.borrow_mut().validator = Some(Box::new(validator));
+    }
+    
+    /// Install a native clock, replacing any previous one
+    pub fn set_clock(&self, clock: impl Clock + 'static) {
+        self.policy.borrow_mut().clock = Some(Box::new(clock));
+    }
+    
+    /// Build, store and serialize a key package for this client
+    fn build_key_package(&self, last_resort: bool) -> Result<Vec<u8>> {
+        // Lifetimes come from our clock, as `SystemTime` is unavailable in wasm
+        let now = self.policy.borrow().now();
+        let mut builder = KeyPackage::builder()
+            .key_package_lifetime(Lifetime::init(now, now + self.key_package_lifetime))
+            .leaf_node_capabilities(self.capabilities.leaf_capabilities())
+            .leaf_node_extensions(self.capabilities.leaf_extensions()?);
+        if last_resort {
+            builder = builder.mark_as_last_resort();
+        }
+        
+        let key_package = builder
             .build(
-                CryptoConfig::default(),
-                &self.crypto_provider,
-                &self.signature_keys,
+                CryptoConfig::with_default_version(self.settings.ciphersuite),
+                &MlsProvider::new(&self.crypto_provider, &self.storage),
+                &*self.signature_keys.borrow(),
                 CredentialWithKey {
                     credential: self.credential.clone(),
-                    signature_key: self.signature_keys.public().into(),
+                    signature_key: self.signature_keys.borrow().public().into(),
                 },
             )
             .map_err(|e| Error::OpenMlsError(e.to_string()))?;
         
         // Store the key package
-        key_package
+        let hash_ref = key_package
             .hash_ref(&self.crypto_provider)
-            .map_err(|e| Error::CryptoError(e.to_string()))
-            .and_then(|hash_ref| {
-                self.storage
-                    .write_key_package(&hash_ref, &key_package)
-                    .map_err(|e| Error::StorageError(e.to_string()))
-            })?;
+            .map_err(|e| Error::CryptoError(e.to_string()))?;
+        self.storage
+            .write_key_package(&hash_ref, &key_package)
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        if last_resort {
+            self.storage.mark_key_package_last_resort(&hash_ref)?;
+        }
         
         key_package
             .tls_serialize_detached()
             .map_err(|e| Error::CodecError(e.to_string()))
     }
+    
+    fn create_group_with_settings(
+        &self,
+        group_id: Vec<u8>,
+        settings: CryptoSettings,
+        required_capabilities: Vec<String>,
+    ) -> Result<MLSGroup> {
+        let crypto_provider = supported_provider(settings.ciphersuite)?;
+        
+        let mut builder = MlsGroupCreateConfig::builder()
+            .crypto_config(CryptoConfig::with_default_version(settings.ciphersuite))
+            .capabilities(self.capabilities.leaf_capabilities())
+            .with_leaf_node_extensions(self.capabilities.leaf_extensions()?)
+            .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+        if let Some(extensions) = required_features_extensions(required_capabilities)? {
+            builder = builder
+                .with_group_context_extensions(extensions)
+                .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+        }
+        let mls_group_config = builder.build();
+        
+        let mut group = MlsGroup::new_with_group_id(
+            &MlsProvider::new(&crypto_provider, &self.storage),
+            &*self.signature_keys.borrow(),
+            &mls_group_config,
+            group_id.clone().into(),
+            CredentialWithKey {
+                credential: self.credential.clone(),
+                signature_key: self.signature_keys.borrow().public().into(),
+            },
+        )
+        .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+        
+        // Store the group
+        group
+            .save(&self.storage)
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        
+        Ok(self.wrap_group(group, crypto_provider))
+    }
+    
+    /// Build a group from a welcome, checking every member's credential
+    fn join_from_welcome(
+        &self,
+        welcome: Welcome,
+        crypto_provider: &CryptoProvider,
+        key_package_refs: &[KeyPackageRef],
+    ) -> Result<MlsGroup> {
+        let mls_group_config = MlsGroupJoinConfig::builder()
+            .crypto_config(CryptoConfig::with_default_version(welcome.ciphersuite()))
+            .build();
+        
+        let mut group = MlsGroup::new_from_welcome(
+            &MlsProvider::new(crypto_provider, &self.storage),
+            &mls_group_config,
+            welcome,
+            Some(&self.storage),
+        )?;
+        
+        {
+            let policy = self.policy.borrow();
+            for member in group.members() {
+                policy.validate(group.group_id().as_slice(), &member.credential, &member.signature_key)?;
+            }
+        }
+        
+        // Store the group
+        group
+            .save(&self.storage)
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        
+        self.storage.mark_key_packages_consumed(key_package_refs)?;
+        
+        Ok(group)
+    }
+    
+    /// Hand out a group sharing this client's storage and credential policy
+    fn wrap_group(&self, group: MlsGroup, crypto_provider: CryptoProvider) -> MLSGroup {
+        let group = Rc::new(RefCell::new(group));
+        let mut live_groups = self.live_groups.borrow_mut();
+        live_groups.retain(|live| live.strong_count() > 0);
+        live_groups.push(Rc::downgrade(&group));
+        
+        MLSGroup::new(
+            group,
+            crypto_provider,
+            self.storage.clone(),
+            self.signature_keys.clone(),
+            self.policy.clone(),
+        )
+    }
+    
+    /// Reload every live group from storage
+    fn refresh_live_groups(&self) -> Result<()> {
+        for live in self.live_groups.borrow().iter().filter_map(Weak::upgrade) {
+            let mut group = live.borrow_mut();
+            let group_id = group.group_id().clone();
+            *group = MlsGroup::load(&self.storage, &group_id)
+                .map_err(|e| Error::StorageError(e.to_string()))?
+                .ok_or_else(|| Error::InvalidState("Group not found in storage".to_string()))?;
+        }
+        Ok(())
+    }
+    
+    /// Move this client's leaf in every stored group to `new_keys`, then
+    /// retire the current key. Runs inside the caller's transaction.
+    fn rotate_groups(&self, new_keys: &SignatureKeyPair) -> Result<Vec<GroupCommit>> {
+        new_keys
+            .store(&self.storage)
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        
+        let credential_with_key = CredentialWithKey {
+            credential: self.credential.clone(),
+            signature_key: new_keys.public().into(),
+        };
+        
+        let mut commits = Vec::new();
+        let mut old_key_in_use = false;
+        for group_id in self.storage.group_ids::<GroupId>()? {
+            let mut group = MlsGroup::load(&self.storage, &group_id)
+                .map_err(|e| Error::StorageError(e.to_string()))?
+                .ok_or_else(|| Error::InvalidState("Group not found in storage".to_string()))?;
+            
+            // Groups we were removed from cannot commit, and a group with a
+            // pending commit cannot start another one. Skip both rather than
+            // fail the whole rotation.
+            if !group.is_active() {
+                continue;
+            }
+            if group.pending_commit().is_some() {
+                old_key_in_use = true;
+                continue;
+            }
+            let crypto_provider = supported_provider(group.ciphersuite())?;
+            
+            let (mls_message_out, _welcome_out, _group_info) = group
+                .self_update_with_new_signer(
+                    &MlsProvider::new(&crypto_provider, &self.storage),
+                    &*self.signature_keys.borrow(),
+                    NewSignerBundle {
+                        signer: new_keys,
+                        credential_with_key: credential_with_key.clone(),
+                    },
+                )
+                .map_err(|e| Error::OpenMlsError(format!("Self update error: {:?}", e)))?;
+            group
+                .merge_pending_commit(&MlsProvider::new(&crypto_provider, &self.storage))
+                .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+            group
+                .save(&self.storage)
+                .map_err(|e| Error::StorageError(e.to_string()))?;
+            
+            commits.push(GroupCommit {
+                group_id: group_id.to_vec(),
+                commit: mls_message_out
+                    .tls_serialize_detached()
+                    .map_err(|e| Error::CodecError(e.to_string()))?,
+            });
+        }
+        
+        // A skipped active group's leaf still carries the old key
+        if !old_key_in_use {
+            SignatureKeyPair::delete(
+                &self.storage,
+                self.signature_keys.borrow().public(),
+                self.signature_keys.borrow().signature_scheme(),
+            )
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        }
+        
+        Ok(commits)
+    }
+    
+    /// Create a fresh credential and signature key pair for `identity`
+    fn generate(
+        identity: Identity,
+        storage: ClientStorage,
+        settings: CryptoSettings,
+    ) -> Result<MLSClient> {
+        // Create credential from the canonical encoding of the identity
+        let identity_bytes = identity.encode();
+        let credential = Credential::new_basic(identity_bytes.clone());
+        
+        // Generate signature key pair
+        let signature_keys = SignatureKeyPair::new(settings.signature_scheme)
+            .map_err(|e| Error::CryptoError(e.to_string()))?;
+        
+        Self::from_parts(identity_bytes, credential, signature_keys, storage, settings)
+    }
+    
+    /// Rebuild the identity stored in `state_bytes`
+    fn restore_into(
+        identity: String,
+        state_bytes: &[u8],
+        storage: ClientStorage,
+    ) -> Result<MLSClient> {
+        let state: ClientState = serde_json::from_slice(state_bytes)
+            .map_err(|e| Error::SerializationError(e.to_string()))?;
+        
+        if state.version != CLIENT_STATE_VERSION {
+            return Err(Error::UnsupportedStorageVersion {
+                namespace: "client_state".to_string(),
+                version: state.version,
+            });
+        }
+        
+        let identity: Identity = identity.parse()?;
+        if !Identity::parse(&state.identity)?.same_member(&identity) {
+            return Err(Error::InvalidState(
+                "Client state belongs to a different identity".to_string(),
+            ));
+        }
+        
+        let credential = Credential::tls_deserialize_exact(&state.credential)
+            .map_err(|e| Error::CodecError(e.to_string()))?;
+        
+        let settings = CryptoSettings::from_config(&MLSConfig {
+            ciphersuite: state.ciphersuite,
+            ..MLSConfig::default()
+        })?;
+        if state.signature_keys.signature_scheme() != settings.signature_scheme {
+            return Err(Error::InvalidState(
+                "Client state signature key does not match its ciphersuite".to_string(),
+            ));
+        }
+        
+        let mut client = Self::from_parts(state.identity, credential, state.signature_keys, storage, settings)?;
+        client.capabilities = state.capabilities;
+        client.key_package_lifetime = state.key_package_lifetime.unwrap_or(DEFAULT_KEY_PACKAGE_LIFETIME);
+        Ok(client)
+    }
+    
+    /// Build a client around an existing credential and signature key pair
+    fn from_parts(
+        identity: Vec<u8>,
+        credential: Credential,
+        signature_keys: SignatureKeyPair,
+        storage: ClientStorage,
+        settings: CryptoSettings,
+    ) -> Result<MLSClient> {
+        let crypto_provider = supported_provider(settings.ciphersuite)?;
+        
+        // Store the signature key pair
+        signature_keys
+            .store(&storage.storage)
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        
+        Ok(MLSClient {
+            identity,
+            crypto_provider,
+            storage: storage.storage,
+            credential,
+            signature_keys: Rc::new(RefCell::new(signature_keys)),
+            settings,
+            cache: storage.cache,
+            encrypted_backend: storage.encrypted,
+            policy: Rc::new(RefCell::new(CredentialPolicy::default())),
+            key_package_lifetime: DEFAULT_KEY_PACKAGE_LIFETIME,
+            capabilities: ClientCapabilities::default(),
+            live_groups: RefCell::new(Vec::new()),
+        })
+    }
+}
+
+/// A client's storage together with handles to the layers that expose
+/// operations of their own
+struct ClientStorage {
+    storage: MLSStorage,
+    cache: Option<Rc<CachedBackend>>,
+    encrypted: Option<Rc<EncryptedBackend>>,
+}
+
+impl ClientStorage {
+    /// An in-memory write-behind cache preloaded with `records`
+    fn cached(records: Vec<StorageRecord>, tenant: &str) -> Result<Self> {
+        let cache = Rc::new(CachedBackend::hydrate(records)?);
+        Ok(Self {
+            storage: MLSStorage::with_shared_backend(cache.clone(), tenant),
+            cache: Some(cache),
+            encrypted: None,
+        })
+    }
+    
+    /// A JS store that is the source of truth for every record
+    fn js(store: JsValue, tenant: &str) -> Result<Self> {
+        Ok(Self {
+            storage: MLSStorage::with_backend(JsStorageBackend::new(store)?, tenant),
+            cache: None,
+            encrypted: None,
+        })
+    }
+    
+    /// Seal every record under `secret` before it reaches `store`, or the
+    /// write-behind cache preloaded with `records` if `store` is `null`
+    fn encrypted(
+        store: JsValue,
+        records: Vec<StorageRecord>,
+        secret: &JsValue,
+        tenant: &str,
+    ) -> Result<Self> {
+        let secret = StorageSecret::from_js(secret)?;
+        
+        let (encrypted, cache) = if store.is_null() || store.is_undefined() {
+            let cache = Rc::new(CachedBackend::hydrate(records)?);
+            (EncryptedBackend::open(cache.clone(), &secret)?, Some(cache))
+        } else {
+            (EncryptedBackend::open(JsStorageBackend::new(store)?, &secret)?, None)
+        };
+        
+        let encrypted = Rc::new(encrypted);
+        Ok(Self {
+            storage: MLSStorage::with_shared_backend(encrypted.clone(), tenant),
+            cache,
+            encrypted: Some(encrypted),
+        })
+    }
 }
 
-/// Represents an MLS group
+/// Represents an MLS group.
+/// The live `MlsGroup` is kept in memory between calls; storage is only
+/// written when an operation changes the group state.
 #[wasm_bindgen]
 pub struct MLSGroup {
-    group_id: Vec<u8>,
-    crypto_provider: OpenMlsRustCrypto,
+    group: Rc<RefCell<MlsGroup>>,
+    crypto_provider: CryptoProvider,
     storage: MLSStorage,
+    /// The owning client's signature key
+    signer: Rc<RefCell<SignatureKeyPair>>,
+    policy: Rc<RefCell<CredentialPolicy>>,
 }
 
 #[wasm_bindgen]
@@ -159,103 +1115,245 @@ impl MLSGroup {
     /// Add a member to the group using their key package
     #[wasm_bindgen(js_name = addMember)]
     pub fn add_member(&self, key_package_bytes: &[u8]) -> Result<JsValue> {
-        let mut group = self.load_group()?;
-        
         let key_package = KeyPackageIn::tls_deserialize_exact(key_package_bytes)
             .map_err(|e| Error::CodecError(e.to_string()))?;
         
-        let (mls_message_out, welcome_out, _group_info) = group
-            .add_members(&self.crypto_provider, &self.storage, &[key_package])?;
-        
-        // Save the updated group state
-        group
-            .save(&self.storage)
-            .map_err(|e| Error::StorageError(e.to_string()))?;
-        
-        let commit_bytes = mls_message_out
-            .tls_serialize_detached()
-            .map_err(|e| Error::CodecError(e.to_string()))?;
-        
-        let welcome_bytes = if let Some(welcome) = welcome_out {
-            vec![welcome
-                .tls_serialize_detached()
-                .map_err(|e| Error::CodecError(e.to_string()))?]
-        } else {
-            vec![]
-        };
-        
-        let commit = MLSCommit {
-            commit: commit_bytes,
-            welcome: welcome_bytes,
-        };
+        let commit = self.transact(|group| {
+            self.check_candidate(group, &key_package)?;
+            
+            let (mls_message_out, welcome_out, _group_info) = group
+                .add_members(&self.provider(), &*self.signer.borrow(), &[key_package])?;
+            
+            commit_from_output(mls_message_out, welcome_out)
+        })?;
         
         to_value(&commit).map_err(|e| Error::SerializationError(e.to_string()))
     }
     
-    /// Remove a member from the group
-    #[wasm_bindgen(js_name = removeMember)]
-    pub fn remove_member(&self, member_id: &str) -> Result<JsValue> {
-        let mut group = self.load_group()?;
-        
-        // Find the member by credential
-        let member_to_remove = group
-            .members()
-            .find(|member| {
-                if let Credential::Basic(data) = &member.credential {
-                    String::from_utf8_lossy(data) == member_id
-                } else {
-                    false
-                }
+    /// Add every device of one user in a single commit. All key packages
+    /// must carry the same user ID.
+    #[wasm_bindgen(js_name = addUserDevices)]
+    pub fn add_user_devices(&self, key_packages: js_sys::Array) -> Result<JsValue> {
+        let key_packages = key_packages
+            .iter()
+            .map(|bytes| {
+                KeyPackageIn::tls_deserialize_exact(&js_sys::Uint8Array::new(&bytes).to_vec())
+                    .map_err(|e| Error::CodecError(e.to_string()))
             })
-            .ok_or_else(|| Error::MemberNotFound(member_id.to_string()))?;
+            .collect::<Result<Vec<_>>>()?;
         
-        let leaf_index = member_to_remove.index;
+        let mut user_ids = key_packages
+            .iter()
+            .map(|key_package| {
+                let identity = credential_identity(&key_package.unverified_credential().credential)?;
+                Ok(Identity::parse(&identity)?.user_id)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        user_ids.sort();
+        user_ids.dedup();
+        if user_ids.len() != 1 {
+            return Err(Error::InvalidIdentity(
+                "Key packages must all belong to one user".to_string(),
+            ));
+        }
         
-        let (mls_message_out, welcome_out, _group_info) = group
-            .remove_members(&self.crypto_provider, &self.storage, &[leaf_index])?;
+        let commit = self.transact(|group| {
+            for key_package in &key_packages {
+                self.check_candidate(group, key_package)?;
+            }
+            
+            let (mls_message_out, welcome_out, _group_info) = group
+                .add_members(&self.provider(), &*self.signer.borrow(), &key_packages)?;
+            
+            commit_from_output(mls_message_out, welcome_out)
+        })?;
         
-        // Save the updated group state
-        group
-            .save(&self.storage)
-            .map_err(|e| Error::StorageError(e.to_string()))?;
+        to_value(&commit).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Add many members in a single commit with one Welcome for all of them.
+    /// Key packages that are malformed, fail the group's checks, belong to a
+    /// current member or repeat an earlier one are skipped and reported in
+    /// the returned `BatchCommit`; the rest are added.
+    #[wasm_bindgen(js_name = addMembers)]
+    pub fn add_members(&self, key_packages: js_sys::Array) -> Result<JsValue> {
+        let batch = self.transact(|group| {
+            let mut items = Vec::new();
+            let mut accepted = Vec::new();
+            let mut seen: Vec<Identity> = group.members().filter_map(|member| member_identity(&member)).collect();
+            
+            for (index, bytes) in key_packages.iter().enumerate() {
+                let candidate = KeyPackageIn::tls_deserialize_exact(&js_sys::Uint8Array::new(&bytes).to_vec())
+                    .map_err(|e| Error::CodecError(e.to_string()))
+                    .and_then(|key_package| {
+                        let identity = credential_identity(&key_package.unverified_credential().credential)?;
+                        Ok((Identity::parse(&identity)?, key_package))
+                    });
+                
+                let (status, member, reason) = match candidate {
+                    Err(e) => (BatchStatus::Rejected, None, Some(e.to_string())),
+                    Ok((identity, _)) if seen.iter().any(|other| other.same_member(&identity)) => {
+                        (BatchStatus::Duplicate, Some(identity.to_string()), None)
+                    }
+                    Ok((identity, key_package)) => match self.check_candidate(group, &key_package) {
+                        Err(e) => (BatchStatus::Rejected, Some(identity.to_string()), Some(e.to_string())),
+                        Ok(()) => {
+                            let member = identity.to_string();
+                            seen.push(identity);
+                            accepted.push(key_package);
+                            (BatchStatus::Added, Some(member), None)
+                        }
+                    },
+                };
+                items.push(BatchItem { index: index as u32, member, status, reason });
+            }
+            
+            let commit = if accepted.is_empty() {
+                None
+            } else {
+                let (mls_message_out, welcome_out, _group_info) = group
+                    .add_members(&self.provider(), &*self.signer.borrow(), &accepted)?;
+                Some(commit_from_output(mls_message_out, welcome_out)?)
+            };
+            Ok(BatchCommit { commit, items })
+        })?;
         
-        let commit_bytes = mls_message_out
-            .tls_serialize_detached()
-            .map_err(|e| Error::CodecError(e.to_string()))?;
+        to_value(&batch).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Remove many members in a single commit. IDs are matched as in
+    /// `removeMember`; IDs matching no single member or naming a member
+    /// already listed are skipped and reported in the returned
+    /// `BatchCommit`, the rest are removed.
+    #[wasm_bindgen(js_name = removeMembers)]
+    pub fn remove_members(&self, member_ids: Vec<String>) -> Result<JsValue> {
+        let batch = self.transact(|group| {
+            let mut items = Vec::new();
+            let mut leaf_indices: Vec<LeafNodeIndex> = Vec::new();
+            
+            for (index, member_id) in member_ids.iter().enumerate() {
+                let (status, member, reason) = match find_member(group, member_id) {
+                    Err(e @ Error::MemberNotFound { .. }) => {
+                        (BatchStatus::Unknown, None, Some(e.to_string()))
+                    }
+                    Err(e) => (BatchStatus::Rejected, None, Some(e.to_string())),
+                    Ok(member) => {
+                        let identity = member_identity(&member).map(|identity| identity.to_string());
+                        if leaf_indices.contains(&member.index) {
+                            (BatchStatus::Duplicate, identity, None)
+                        } else {
+                            leaf_indices.push(member.index);
+                            (BatchStatus::Removed, identity, None)
+                        }
+                    }
+                };
+                items.push(BatchItem { index: index as u32, member, status, reason });
+            }
+            
+            let commit = if leaf_indices.is_empty() {
+                None
+            } else {
+                let (mls_message_out, welcome_out, _group_info) = group
+                    .remove_members(&self.provider(), &*self.signer.borrow(), &leaf_indices)?;
+                Some(commit_from_output(mls_message_out, welcome_out)?)
+            };
+            Ok(BatchCommit { commit, items })
+        })?;
         
-        let welcome_bytes = if let Some(welcome) = welcome_out {
-            vec![welcome
-                .tls_serialize_detached()
-                .map_err(|e| Error::CodecError(e.to_string()))?]
-        } else {
-            vec![]
-        };
+        to_value(&batch).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Remove a member from the group by identity, e.g. `alice#laptop` for
+    /// one device of `alice`. A bare user ID only matches a user with a
+    /// single device.
+    #[wasm_bindgen(js_name = removeMember)]
+    pub fn remove_member(&self, member_id: &str) -> Result<JsValue> {
+        let commit = self.transact(|group| {
+            let member_to_remove = find_member(group, member_id)?;
+            
+            let leaf_index = member_to_remove.index;
+            
+            let (mls_message_out, welcome_out, _group_info) = group
+                .remove_members(&self.provider(), &*self.signer.borrow(), &[leaf_index])?;
+            
+            commit_from_output(mls_message_out, welcome_out)
+        })?;
         
-        let commit = MLSCommit {
-            commit: commit_bytes,
-            welcome: welcome_bytes,
-        };
+        to_value(&commit).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// Remove every device of a user in a single commit
+    #[wasm_bindgen(js_name = removeUser)]
+    pub fn remove_user(&self, user_id: &str) -> Result<JsValue> {
+        let user_id = Identity::new(user_id, None, None)?.user_id;
+        let commit = self.transact(|group| {
+            let leaf_indices: Vec<LeafNodeIndex> = group
+                .members()
+                .filter(|member| {
+                    member_identity(member).map_or(false, |identity| identity.user_id == user_id)
+                })
+                .map(|member| member.index)
+                .collect();
+            if leaf_indices.is_empty() {
+                return Err(Error::MemberNotFound {
+                    query: user_id.clone(),
+                    candidates: Vec::new(),
+                });
+            }
+            
+            let (mls_message_out, welcome_out, _group_info) = group
+                .remove_members(&self.provider(), &*self.signer.borrow(), &leaf_indices)?;
+            
+            commit_from_output(mls_message_out, welcome_out)
+        })?;
         
         to_value(&commit).map_err(|e| Error::SerializationError(e.to_string()))
     }
     
+    /// List the devices of every user in the group as `UserDevices` objects,
+    /// sorted by user ID
+    #[wasm_bindgen(js_name = listDevices)]
+    pub fn list_devices(&self) -> Result<JsValue> {
+        let mut users: BTreeMap<String, UserDevices> = BTreeMap::new();
+        for member in self.group.borrow().members() {
+            if let Some(identity) = member_identity(&member) {
+                let user = users.entry(identity.user_id.clone()).or_insert_with(|| UserDevices {
+                    user_id: identity.user_id,
+                    display_name: None,
+                    devices: Vec::new(),
+                });
+                user.devices.push(identity.device_id);
+                user.display_name = user.display_name.take().or(identity.display_name);
+            }
+        }
+        
+        let users: Vec<UserDevices> = users
+            .into_values()
+            .map(|mut user| {
+                user.devices.sort();
+                user
+            })
+            .collect();
+        to_value(&users).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
     /// Encrypt a message for the group
     #[wasm_bindgen(js_name = encryptMessage)]
     pub fn encrypt_message(&self, plaintext: &[u8]) -> Result<JsValue> {
-        let mut group = self.load_group()?;
-        
-        let mls_message_out = group
-            .create_message(&self.crypto_provider, &self.storage, plaintext)
-            .map_err(|e| Error::OpenMlsError(e.to_string()))?;
-        
-        let ciphertext_bytes = mls_message_out
-            .tls_serialize_detached()
-            .map_err(|e| Error::CodecError(e.to_string()))?;
-        
-        let ciphertext = MLSCiphertext {
-            data: ciphertext_bytes,
-            epoch: group.epoch().as_u64(),
-        };
+        let ciphertext = self.transact_message(|group| {
+            let mls_message_out = group
+                .create_message(&self.provider(), &*self.signer.borrow(), plaintext)
+                .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+            
+            let ciphertext_bytes = mls_message_out
+                .tls_serialize_detached()
+                .map_err(|e| Error::CodecError(e.to_string()))?;
+            
+            Ok(MLSCiphertext {
+                data: ciphertext_bytes,
+                epoch: group.epoch().as_u64(),
+            })
+        })?;
         
         to_value(&ciphertext).map_err(|e| Error::SerializationError(e.to_string()))
     }
@@ -263,73 +1361,407 @@ impl MLSGroup {
     /// Decrypt a message from the group
     #[wasm_bindgen(js_name = decryptMessage)]
     pub fn decrypt_message(&self, ciphertext_bytes: &[u8]) -> Result<Vec<u8>> {
-        let mut group = self.load_group()?;
-        
         let mls_message = MlsMessageIn::tls_deserialize_exact(ciphertext_bytes)
             .map_err(|e| Error::CodecError(e.to_string()))?;
         
-        let unverified_message = group
-            .parse_message(mls_message, &self.crypto_provider, &self.storage)
-            .map_err(|e| Error::OpenMlsError(e.to_string()))?;
-        
-        let processed_message = group
-            .process_unverified_message(unverified_message, None, &self.crypto_provider, &self.storage)?;
-        
-        // Save the updated group state if needed
-        group
-            .save(&self.storage)
-            .map_err(|e| Error::StorageError(e.to_string()))?;
-        
-        match processed_message.into_content() {
-            ProcessedMessageContent::ApplicationMessage(app_msg) => {
-                Ok(app_msg.into_bytes())
-            }
-            ProcessedMessageContent::ProposalMessage(_) => {
-                Err(Error::InvalidMessageType("Received proposal, expected application message".to_string()))
-            }
-            ProcessedMessageContent::ExhumerMessage(_) => {
-                Err(Error::InvalidMessageType("Received exhumer message".to_string()))
-            }
-            ProcessedMessageContent::StagedCommitMessage(_) => {
-                Err(Error::InvalidMessageType("Received commit, expected application message".to_string()))
+        self.transact_message(|group| {
+            let unverified_message = group
+                .parse_message(mls_message, &self.provider(), &self.storage)
+                .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+            
+            let processed_message = group
+                .process_unverified_message(unverified_message, None, &self.provider(), &self.storage)?;
+            
+            match processed_message.into_content() {
+                ProcessedMessageContent::ApplicationMessage(app_msg) => {
+                    Ok(app_msg.into_bytes())
+                }
+                ProcessedMessageContent::ProposalMessage(_) => {
+                    Err(Error::InvalidMessageType("Received proposal, expected application message".to_string()))
+                }
+                ProcessedMessageContent::ExhumerMessage(_) => {
+                    Err(Error::InvalidMessageType("Received exhumer message".to_string()))
+                }
+                ProcessedMessageContent::StagedCommitMessage(_) => {
+                    Err(Error::InvalidMessageType("Received commit, expected application message".to_string()))
+                }
             }
-        }
+        })
     }
     
     /// Process a pending commit
     #[wasm_bindgen(js_name = processCommit)]
     pub fn process_commit(&self, commit_bytes: &[u8]) -> Result<()> {
-        let mut group = self.load_group()?;
-        
         let mls_message = MlsMessageIn::tls_deserialize_exact(commit_bytes)
             .map_err(|e| Error::CodecError(e.to_string()))?;
         
-        let unverified_message = group
-            .parse_message(mls_message, &self.crypto_provider, &self.storage)
-            .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+        self.transact(|group| {
+            let unverified_message = group
+                .parse_message(mls_message, &self.provider(), &self.storage)
+                .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+            
+            let processed_message = group
+                .process_unverified_message(unverified_message, None, &self.provider(), &self.storage)?;
+            
+            if let ProcessedMessageContent::StagedCommitMessage(staged_commit) =
+                processed_message.into_content()
+            {
+                // Members added or re-keyed by someone else are subject to
+                // our policy too
+                let policy = self.policy.borrow();
+                let added = staged_commit
+                    .add_proposals()
+                    .map(|add| add.add_proposal().key_package().leaf_node().clone());
+                let required = required_features(group);
+                for leaf_node in added.chain(staged_commit.update_path_leaf_node().cloned()) {
+                    policy.validate(
+                        group.group_id().as_slice(),
+                        leaf_node.credential(),
+                        leaf_node.signature_key().as_slice(),
+                    )?;
+                    check_features(&features_in(leaf_node.extensions(), FEATURES_EXTENSION_TYPE), &required)?;
+                }
+                
+                group
+                    .merge_staged_commit(&self.provider(), *staged_commit)
+                    .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+            }
+            
+            Ok(())
+        })
+    }
+    
+    /// Fingerprints of every member's credential and signature key, as
+    /// `MemberFingerprint` objects, flagging keys that changed since the user
+    /// last verified them
+    #[wasm_bindgen(js_name = memberFingerprints)]
+    pub fn member_fingerprints(&self) -> Result<JsValue> {
+        let fingerprints = self
+            .group
+            .borrow()
+            .members()
+            .map(|member| {
+                // Members whose identity does not parse are listed as is
+                let raw = credential_identity(&member.credential)?;
+                let (identity, display_name) = match Identity::parse(&raw) {
+                    Ok(identity) => (identity.to_string(), identity.display_name),
+                    Err(_) => (String::from_utf8_lossy(&raw).into_owned(), None),
+                };
+                let fingerprint = fingerprint_of(&member)?;
+                let verified = self.storage.read_verified_fingerprint(identity.as_bytes())?;
+                
+                Ok(MemberFingerprint {
+                    identity,
+                    display_name,
+                    numeric: numeric(&fingerprint),
+                    qr_payload: qr_payload(&fingerprint),
+                    verified: verified.as_deref() == Some(fingerprint.as_slice()),
+                    key_changed: verified.map_or(false, |verified| verified != fingerprint),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
         
-        group.process_unverified_message(unverified_message, None, &self.crypto_provider, &self.storage)?;
+        to_value(&fingerprints).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// The features and extension types every member supports, as a
+    /// `GroupCapabilities` object, with the features the group requires
+    #[wasm_bindgen(js_name = commonCapabilities)]
+    pub fn common_capabilities(&self) -> Result<JsValue> {
+        let group = self.group.borrow();
+        let mut features: Option<BTreeSet<String>> = None;
+        let mut extensions: Option<BTreeSet<u16>> = None;
+        for member in group.members() {
+            let leaf = group
+                .public_group()
+                .leaf(member.index)
+                .ok_or_else(|| Error::InvalidState("Member has no leaf".to_string()))?;
+            let leaf_features = features_in(leaf.extensions(), FEATURES_EXTENSION_TYPE);
+            let leaf_extensions = leaf.capabilities().extensions().iter().map(|&e| u16::from(e)).collect();
+            features = Some(intersect(features, leaf_features));
+            extensions = Some(intersect(extensions, leaf_extensions));
+        }
         
-        // Save the updated group state
-        group
-            .save(&self.storage)
-            .map_err(|e| Error::StorageError(e.to_string()))?;
+        let capabilities = GroupCapabilities {
+            features: features.unwrap_or_default().into_iter().collect(),
+            extensions: extensions.unwrap_or_default().into_iter().collect(),
+            required: required_features(&group).into_iter().collect(),
+        };
+        to_value(&capabilities).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+    
+    /// The data of the app-defined leaf extension of `extension_type` that
+    /// `member_id` advertises, if any
+    #[wasm_bindgen(js_name = memberExtension)]
+    pub fn member_extension(&self, member_id: &str, extension_type: u16) -> Result<Option<Vec<u8>>> {
+        let group = self.group.borrow();
+        let member = find_member(&group, member_id)?;
+        Ok(group
+            .public_group()
+            .leaf(member.index)
+            .and_then(|leaf| leaf.extensions().unknown(extension_type))
+            .map(|extension| extension.0.clone()))
+    }
+    
+    /// A code over all members' fingerprints that every member computes
+    /// identically, for comparing the whole group at once
+    #[wasm_bindgen(js_name = verificationCode)]
+    pub fn verification_code(&self) -> Result<String> {
+        let group = self.group.borrow();
+        let fingerprints = group
+            .members()
+            .map(|member| fingerprint_of(&member))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(group_code(group.group_id().as_slice(), &fingerprints))
+    }
+    
+    /// Remember the current key of `member_id` as verified by the user. The
+    /// record is kept per identity, across groups.
+    #[wasm_bindgen(js_name = markVerified)]
+    pub fn mark_verified(&self, member_id: &str) -> Result<()> {
+        let member = find_member(&self.group.borrow(), member_id)?;
+        let identity = member_identity(&member)
+            .ok_or_else(|| Error::InvalidIdentity(member_id.to_string()))?;
         
-        Ok(())
+        self.storage
+            .write_verified_fingerprint(identity.to_string().as_bytes(), &fingerprint_of(&member)?)
     }
     
     /// Get the current epoch of the group
     #[wasm_bindgen(js_name = getCurrentEpoch)]
     pub fn get_current_epoch(&self) -> Result<u64> {
-        let group = self.load_group()?;
-        Ok(group.epoch().as_u64())
+        Ok(self.group.borrow().epoch().as_u64())
     }
     
-    /// Helper method to load the group from storage
-    fn load_group(&self) -> Result<MlsGroup> {
-        let group_id: GroupId = self.group_id.clone().into();
-        MlsGroup::load(&self.storage, &group_id)
-            .map_err(|e| Error::StorageError(e.to_string()))?
-            .ok_or_else(|| Error::InvalidState("Group not found in storage".to_string()))
+    /// Get the name of the group's ciphersuite
+    #[wasm_bindgen(getter)]
+    pub fn ciphersuite(&self) -> String {
+        ciphersuite_name(self.group.borrow().ciphersuite())
     }
-}
\ No newline at end of file
+    
+    /// Whether the group uses a hybrid post-quantum KEM
+    #[wasm_bindgen(getter, js_name = isPostQuantum)]
+    pub fn is_post_quantum(&self) -> bool {
+        is_hybrid(self.group.borrow().ciphersuite())
+    }
+    
+    /// Get the number of members in the current epoch
+    #[wasm_bindgen(js_name = getMemberCount)]
+    pub fn get_member_count(&self) -> usize {
+        self.group.borrow().members().count()
+    }
+}
+
+impl MLSGroup {
+    fn new(
+        group: Rc<RefCell<MlsGroup>>,
+        crypto_provider: CryptoProvider,
+        storage: MLSStorage,
+        signer: Rc<RefCell<SignatureKeyPair>>,
+        policy: Rc<RefCell<CredentialPolicy>>,
+    ) -> Self {
+        Self {
+            group,
+            crypto_provider,
+            storage,
+            signer,
+            policy,
+        }
+    }
+    
+    /// The group's crypto provider paired with its storage
+    fn provider(&self) -> MlsProvider<'_> {
+        MlsProvider::new(&self.crypto_provider, &self.storage)
+    }
+    
+    /// Check that a key package fits the group, is within its lifetime,
+    /// advertises the required features and passes the credential policy
+    fn check_candidate(&self, group: &MlsGroup, key_package: &KeyPackageIn) -> Result<()> {
+        if key_package.ciphersuite() != group.ciphersuite() {
+            return Err(Error::CiphersuiteMismatch {
+                expected: ciphersuite_name(group.ciphersuite()),
+                found: ciphersuite_name(key_package.ciphersuite()),
+            });
+        }
+        
+        let lifetime = key_package.life_time();
+        check_lifetime(self.policy.borrow().now(), lifetime.not_before(), lifetime.not_after())?;
+        check_features(
+            &features_in(key_package.leaf_node().extensions(), FEATURES_EXTENSION_TYPE),
+            &required_features(group),
+        )?;
+        
+        let candidate = key_package.unverified_credential();
+        self.policy.borrow().validate(
+            group.group_id().as_slice(),
+            &candidate.credential,
+            candidate.signature_key.as_slice(),
+        )
+    }
+    
+    /// Run `op` on the live group inside a storage transaction. A commit
+    /// created by `op` is merged so the group moves to the new epoch. The
+    /// group state is saved and the transaction committed only if `op`
+    /// succeeds; on any error the transaction is rolled back and the live
+    /// group is reloaded from the last committed state.
+    fn transact<T>(&self, op: impl FnOnce(&mut MlsGroup) -> Result<T>) -> Result<T> {
+        self.run_transaction(op, |group| {
+            if group.pending_commit().is_some() {
+                group
+                    .merge_pending_commit(&self.provider())
+                    .map_err(|e| Error::OpenMlsError(e.to_string()))?;
+            }
+            group
+                .save(&self.storage)
+                .map_err(|e| Error::StorageError(e.to_string()))
+        })
+    }
+    
+    /// Like `transact`, for sending and receiving application messages.
+    /// These only advance the secret tree and its generations, so only the
+    /// message secrets are written back rather than the whole group state.
+    fn transact_message<T>(&self, op: impl FnOnce(&mut MlsGroup) -> Result<T>) -> Result<T> {
+        self.run_transaction(op, |group| {
+            self.storage
+                .write_message_secrets(group.group_id(), group.message_secrets())
+        })
+    }
+    
+    /// Run `op` and then `persist` on the live group inside a storage
+    /// transaction, rolling back and reloading the group if either fails
+    fn run_transaction<T>(
+        &self,
+        op: impl FnOnce(&mut MlsGroup) -> Result<T>,
+        persist: impl FnOnce(&mut MlsGroup) -> Result<()>,
+    ) -> Result<T> {
+        let mut group = self.group.borrow_mut();
+        self.storage.begin_transaction()?;
+        
+        let result = op(&mut group).and_then(|value| {
+            persist(&mut group)?;
+            self.storage.commit_transaction()?;
+            Ok(value)
+        });
+        
+        if result.is_err() {
+            self.storage.rollback_transaction();
+            
+            // The live group may hold half-applied state
+            let group_id = group.group_id().clone();
+            *group = MlsGroup::load(&self.storage, &group_id)
+                .map_err(|e| Error::StorageError(e.to_string()))?
+                .ok_or_else(|| Error::InvalidState("Group not found in storage".to_string()))?;
+        }
+        
+        result
+    }
+}
+
+/// The tenant a client's records are scoped to: `tenant` if the application
+/// names one, else the normalized identity, so that every spelling of an
+/// identity opens the same records
+fn tenant_for(identity: &str, tenant: Option<String>) -> Result<String> {
+    match tenant {
+        Some(tenant) => Ok(tenant),
+        None => Ok(identity.parse::<Identity>()?.to_string()),
+    }
+}
+
+/// The identity a member's credential names, if it can be parsed
+fn member_identity(member: &Member) -> Option<Identity> {
+    let identity = credential_identity(&member.credential).ok()?;
+    Identity::parse(&identity).ok()
+}
+
+/// Find the one member named by `member_id` after normalization. A bare user
+/// ID matches any device of that user, and is ambiguous if several match.
+fn find_member(group: &MlsGroup, member_id: &str) -> Result<Member> {
+    let query: Identity = member_id.parse()?;
+    let mut matches: Vec<(Identity, Member)> = group
+        .members()
+        .filter_map(|member| member_identity(&member).map(|identity| (identity, member)))
+        .filter(|(identity, _)| {
+            identity.user_id == query.user_id
+                && (query.device_id.is_none() || identity.device_id == query.device_id)
+        })
+        .collect();
+    
+    // An exact match wins over other devices of the same user
+    if let Some(exact) = matches.iter().position(|(identity, _)| identity.same_member(&query)) {
+        return Ok(matches.swap_remove(exact).1);
+    }
+    
+    match matches.len() {
+        1 => Ok(matches.remove(0).1),
+        _ => Err(Error::MemberNotFound {
+            query: query.to_string(),
+            candidates: matches.iter().map(|(identity, _)| identity.to_string()).collect(),
+        }),
+    }
+}
+
+/// Fingerprint of a member's credential and signature key
+fn fingerprint_of(member: &Member) -> Result<Vec<u8>> {
+    credential_fingerprint(&member.credential, &member.signature_key)
+}
+
+/// Fingerprint of a credential together with the key it is presented with
+fn credential_fingerprint(credential: &Credential, signature_key: &[u8]) -> Result<Vec<u8>> {
+    let credential = credential
+        .tls_serialize_detached()
+        .map_err(|e| Error::CodecError(e.to_string()))?;
+    Ok(member_fingerprint(&credential, signature_key))
+}
+
+/// The features every member of `group` must advertise
+fn required_features(group: &MlsGroup) -> BTreeSet<String> {
+    features_in(group.export_group_context().extensions(), REQUIRED_FEATURES_EXTENSION_TYPE)
+}
+
+/// Intersect `set` into what is `common` so far, `None` meaning no set yet
+fn intersect<T: Ord + Clone>(common: Option<BTreeSet<T>>, set: BTreeSet<T>) -> BTreeSet<T> {
+    match common {
+        Some(common) => common.intersection(&set).cloned().collect(),
+        None => set,
+    }
+}
+
+/// The registry values of the types in `extensions`
+fn extension_types(extensions: &Extensions) -> Vec<u16> {
+    extensions
+        .iter()
+        .map(|extension| u16::from(extension.extension_type()))
+        .collect()
+}
+
+/// The provider for `ciphersuite`, failing if it cannot be used
+fn supported_provider(ciphersuite: Ciphersuite) -> Result<CryptoProvider> {
+    let crypto_provider = CryptoProvider::for_ciphersuite(ciphersuite);
+    crypto_provider
+        .supports(ciphersuite)
+        .map_err(|_| Error::UnsupportedCiphersuite(ciphersuite_name(ciphersuite)))?;
+    Ok(crypto_provider)
+}
+
+/// Serialize the outcome of a membership change
+fn commit_from_output(
+    mls_message_out: MlsMessageOut,
+    welcome_out: Option<MlsMessageOut>,
+) -> Result<MLSCommit> {
+    let commit_bytes = mls_message_out
+        .tls_serialize_detached()
+        .map_err(|e| Error::CodecError(e.to_string()))?;
+    
+    let welcome_bytes = if let Some(welcome) = welcome_out {
+        vec![welcome
+            .tls_serialize_detached()
+            .map_err(|e| Error::CodecError(e.to_string()))?]
+    } else {
+        vec![]
+    };
+    
+    Ok(MLSCommit {
+        commit: commit_bytes,
+        welcome: welcome_bytes,
+    })
+}
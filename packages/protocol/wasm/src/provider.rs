@@ -0,0 +1,256 @@
+use crate::error::Error;
+use crate::storage::MLSStorage;
+use openmls::prelude::Ciphersuite;
+use openmls_libcrux_crypto::Provider as LibcruxProvider;
+use openmls_rust_crypto::OpenMlsRustCrypto;
+use openmls_traits::crypto::OpenMlsCrypto;
+use openmls_traits::random::OpenMlsRand;
+use openmls_traits::types::{
+    AeadType, CryptoError, ExporterSecret, HashType, HpkeCiphertext, HpkeConfig, HpkeKeyPair,
+    KemOutput, SignatureScheme,
+};
+use openmls_traits::OpenMlsProvider;
+use tls_codec::SecretVLBytes;
+
+/// Hybrid post-quantum ciphersuites, which are only implemented by libcrux
+pub const HYBRID_CIPHERSUITES: &[Ciphersuite] =
+    &[Ciphersuite::MLS_256_XWING_CHACHA20POLY1305_SHA256_Ed25519];
+
+/// The crypto and randomness provider backing a client or group.
+///
+/// Classical ciphersuites use the RustCrypto provider, while hybrid
+/// post-quantum KEM ciphersuites such as X-Wing use libcrux.
+#[derive(Clone)]
+pub enum CryptoProvider {
+    Classic(OpenMlsRustCrypto),
+    Hybrid(LibcruxProvider),
+}
+
+impl Default for CryptoProvider {
+    fn default() -> Self {
+        CryptoProvider::Classic(OpenMlsRustCrypto::default())
+    }
+}
+
+impl CryptoProvider {
+    /// Pick the provider implementing `ciphersuite`
+    pub fn for_ciphersuite(ciphersuite: Ciphersuite) -> Self {
+        if is_hybrid(ciphersuite) {
+            CryptoProvider::Hybrid(LibcruxProvider::default())
+        } else {
+            CryptoProvider::default()
+        }
+    }
+}
+
+/// Whether `ciphersuite` uses a hybrid post-quantum KEM
+pub fn is_hybrid(ciphersuite: Ciphersuite) -> bool {
+    HYBRID_CIPHERSUITES.contains(&ciphersuite)
+}
+
+/// A `CryptoProvider` together with the storage it works against, for
+/// openmls calls that need a full `OpenMlsProvider`
+pub struct MlsProvider<'a> {
+    crypto: &'a CryptoProvider,
+    storage: &'a MLSStorage,
+}
+
+impl<'a> MlsProvider<'a> {
+    pub fn new(crypto: &'a CryptoProvider, storage: &'a MLSStorage) -> Self {
+        Self { crypto, storage }
+    }
+}
+
+impl OpenMlsProvider for MlsProvider<'_> {
+    type CryptoProvider = CryptoProvider;
+    type RandProvider = CryptoProvider;
+    type StorageProvider = MLSStorage;
+
+    fn storage(&self) -> &Self::StorageProvider {
+        self.storage
+    }
+
+    fn crypto(&self) -> &Self::CryptoProvider {
+        self.crypto
+    }
+
+    fn rand(&self) -> &Self::RandProvider {
+        self.crypto
+    }
+}
+
+macro_rules! crypto {
+    ($self:ident, $crypto:ident => $body:expr) => {
+        match $self {
+            CryptoProvider::Classic(provider) => {
+                let $crypto = provider.crypto();
+                $body
+            }
+            CryptoProvider::Hybrid(provider) => {
+                let $crypto = provider.crypto();
+                $body
+            }
+        }
+    };
+}
+
+impl OpenMlsCrypto for CryptoProvider {
+    fn supports(&self, ciphersuite: Ciphersuite) -> Result<(), CryptoError> {
+        crypto!(self, c => c.supports(ciphersuite))
+    }
+
+    fn supported_ciphersuites(&self) -> Vec<Ciphersuite> {
+        crypto!(self, c => c.supported_ciphersuites())
+    }
+
+    fn hkdf_extract(
+        &self,
+        hash_type: HashType,
+        salt: &[u8],
+        ikm: &[u8],
+    ) -> Result<SecretVLBytes, CryptoError> {
+        crypto!(self, c => c.hkdf_extract(hash_type, salt, ikm))
+    }
+
+    fn hkdf_expand(
+        &self,
+        hash_type: HashType,
+        prk: &[u8],
+        info: &[u8],
+        okm_len: usize,
+    ) -> Result<SecretVLBytes, CryptoError> {
+        crypto!(self, c => c.hkdf_expand(hash_type, prk, info, okm_len))
+    }
+
+    fn hash(&self, hash_type: HashType, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        crypto!(self, c => c.hash(hash_type, data))
+    }
+
+    fn aead_encrypt(
+        &self,
+        alg: AeadType,
+        key: &[u8],
+        data: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        crypto!(self, c => c.aead_encrypt(alg, key, data, nonce, aad))
+    }
+
+    fn aead_decrypt(
+        &self,
+        alg: AeadType,
+        key: &[u8],
+        ct_tag: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        crypto!(self, c => c.aead_decrypt(alg, key, ct_tag, nonce, aad))
+    }
+
+    fn signature_key_gen(&self, alg: SignatureScheme) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        crypto!(self, c => c.signature_key_gen(alg))
+    }
+
+    fn verify_signature(
+        &self,
+        alg: SignatureScheme,
+        data: &[u8],
+        pk: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CryptoError> {
+        crypto!(self, c => c.verify_signature(alg, data, pk, signature))
+    }
+
+    fn sign(&self, alg: SignatureScheme, data: &[u8], key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        crypto!(self, c => c.sign(alg, data, key))
+    }
+
+    fn hpke_seal(
+        &self,
+        config: HpkeConfig,
+        pk_r: &[u8],
+        info: &[u8],
+        aad: &[u8],
+        ptxt: &[u8],
+    ) -> Result<HpkeCiphertext, CryptoError> {
+        crypto!(self, c => c.hpke_seal(config, pk_r, info, aad, ptxt))
+    }
+
+    fn hpke_open(
+        &self,
+        config: HpkeConfig,
+        input: &HpkeCiphertext,
+        sk_r: &[u8],
+        info: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        crypto!(self, c => c.hpke_open(config, input, sk_r, info, aad))
+    }
+
+    fn hpke_setup_sender_and_export(
+        &self,
+        config: HpkeConfig,
+        pk_r: &[u8],
+        info: &[u8],
+        exporter_context: &[u8],
+        exporter_length: usize,
+    ) -> Result<(KemOutput, ExporterSecret), CryptoError> {
+        crypto!(self, c => c.hpke_setup_sender_and_export(
+            config,
+            pk_r,
+            info,
+            exporter_context,
+            exporter_length,
+        ))
+    }
+
+    fn hpke_setup_receiver_and_export(
+        &self,
+        config: HpkeConfig,
+        enc: &[u8],
+        sk_r: &[u8],
+        info: &[u8],
+        exporter_context: &[u8],
+        exporter_length: usize,
+    ) -> Result<ExporterSecret, CryptoError> {
+        crypto!(self, c => c.hpke_setup_receiver_and_export(
+            config,
+            enc,
+            sk_r,
+            info,
+            exporter_context,
+            exporter_length,
+        ))
+    }
+
+    fn derive_hpke_keypair(
+        &self,
+        config: HpkeConfig,
+        ikm: &[u8],
+    ) -> Result<HpkeKeyPair, CryptoError> {
+        crypto!(self, c => c.derive_hpke_keypair(config, ikm))
+    }
+}
+
+impl OpenMlsRand for CryptoProvider {
+    type Error = Error;
+
+    fn random_array<const N: usize>(&self) -> Result<[u8; N], Self::Error> {
+        match self {
+            CryptoProvider::Classic(provider) => provider.rand().random_array().map_err(rand_error),
+            CryptoProvider::Hybrid(provider) => provider.rand().random_array().map_err(rand_error),
+        }
+    }
+
+    fn random_vec(&self, len: usize) -> Result<Vec<u8>, Self::Error> {
+        match self {
+            CryptoProvider::Classic(provider) => provider.rand().random_vec(len).map_err(rand_error),
+            CryptoProvider::Hybrid(provider) => provider.rand().random_vec(len).map_err(rand_error),
+        }
+    }
+}
+
+fn rand_error(error: impl std::fmt::Debug) -> Error {
+    Error::CryptoError(format!("Randomness error: {:?}", error))
+}
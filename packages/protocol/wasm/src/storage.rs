@@ -1,24 +1,669 @@
-use openmls_rust_crypto::MemoryStorage;
+use crate::error::{Error, Result};
+use crate::migrations::{decode_record, encode_record};
 use openmls_traits::storage::{StorageProvider, CURRENT_VERSION};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::RwLock;
 
-/// A wrapper around the OpenMLS memory storage implementation.
-/// This provides in-memory storage for MLS groups and key material.
-/// In a production environment, this could be replaced with persistent storage.
-#[derive(Default, Clone)]
+/// Magic bytes at the start of every storage snapshot
+const SNAPSHOT_MAGIC: &[u8; 4] = b"OCMS";
+
+/// Version of the snapshot layout written by `export_snapshot`
+const SNAPSHOT_VERSION: u16 = 2;
+
+/// Snapshot layout of the openmls memory storage, still accepted on import
+const SNAPSHOT_VERSION_V1: u16 = 1;
+
+/// Labels the openmls memory storage prefixed its keys with, as found in
+/// version 1 snapshots, and the namespaces those records live in now
+const V1_LABELS: &[(&[u8], &str)] = &[
+    (b"GroupState", GROUP_STATE_NAMESPACE),
+    (b"KeyPackage", KEY_PACKAGE_NAMESPACE),
+    (b"SignatureKeyPair", SIGNATURE_KEY_NAMESPACE),
+    (b"EncryptionKeyPair", ENCRYPTION_KEY_NAMESPACE),
+    (b"EpochKeyPairs", EPOCH_KEY_PAIRS_NAMESPACE),
+    (b"Psk", PSK_NAMESPACE),
+];
+
+/// Namespaces under which the different kinds of MLS records are stored
+pub const GROUP_STATE_NAMESPACE: &str = "group_state";
+pub const KEY_PACKAGE_NAMESPACE: &str = "key_package";
+pub const SIGNATURE_KEY_NAMESPACE: &str = "signature_key_pair";
+pub const ENCRYPTION_KEY_NAMESPACE: &str = "encryption_key_pair";
+pub const EPOCH_KEY_PAIRS_NAMESPACE: &str = "epoch_key_pairs";
+pub const PSK_NAMESPACE: &str = "psk";
+pub const MESSAGE_SECRETS_NAMESPACE: &str = "message_secrets";
+pub const CONSUMED_KEY_PACKAGE_NAMESPACE: &str = "consumed_key_package";
+pub const VERIFIED_KEY_NAMESPACE: &str = "verified_key";
+pub const LAST_RESORT_KEY_PACKAGE_NAMESPACE: &str = "last_resort_key_package";
+
+/// Tenant used when a storage is not bound to a client identity
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Separates the tenant from the record namespace in backend namespaces
+const TENANT_SEPARATOR: char = '/';
+
+/// A single stored record: namespace, key and value
+pub type StorageRecord = (String, Vec<u8>, Vec<u8>);
+
+/// A byte-oriented key-value store that holds the serialized MLS records.
+/// Keys are scoped by a namespace naming the kind of record.
+pub trait StorageBackend {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<()>;
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<()>;
+
+    /// List every record in the store
+    fn entries(&self) -> Result<Vec<StorageRecord>>;
+
+    /// Remove every record from the store
+    fn clear(&self) -> Result<()>;
+}
+
+/// The default in-memory backend
+#[derive(Default)]
+pub struct MemoryBackend {
+    values: RwLock<HashMap<(String, Vec<u8>), Vec<u8>>>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let values = self.values.read().map_err(|_| lock_poisoned())?;
+        Ok(values.get(&(namespace.to_string(), key.to_vec())).cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut values = self.values.write().map_err(|_| lock_poisoned())?;
+        values.insert((namespace.to_string(), key.to_vec()), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<()> {
+        let mut values = self.values.write().map_err(|_| lock_poisoned())?;
+        values.remove(&(namespace.to_string(), key.to_vec()));
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<StorageRecord>> {
+        let values = self.values.read().map_err(|_| lock_poisoned())?;
+        Ok(values
+            .iter()
+            .map(|((namespace, key), value)| (namespace.clone(), key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn clear(&self) -> Result<()> {
+        let mut values = self.values.write().map_err(|_| lock_poisoned())?;
+        values.clear();
+        Ok(())
+    }
+}
+
+/// Lets a backend be shared between the storage and the code that owns it
+impl<B: StorageBackend + ?Sized> StorageBackend for Rc<B> {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        (**self).get(namespace, key)
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        (**self).put(namespace, key, value)
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<()> {
+        (**self).delete(namespace, key)
+    }
+
+    fn entries(&self) -> Result<Vec<StorageRecord>> {
+        (**self).entries()
+    }
+
+    fn clear(&self) -> Result<()> {
+        (**self).clear()
+    }
+}
+
+/// The tenant part of a backend namespace
+fn tenant_of(namespace: &str) -> Option<&str> {
+    namespace
+        .rsplit_once(TENANT_SEPARATOR)
+        .map(|(tenant, _)| tenant)
+}
+
+fn lock_poisoned() -> Error {
+    Error::StorageError("Storage lock poisoned".to_string())
+}
+
+/// Storage for MLS groups and key material.
+/// Records are serialized and handed to a `StorageBackend`, which is an
+/// in-memory map by default. Clones share the same backend, so a client and
+/// all of its groups always observe the same state.
+///
+/// Every record is scoped to a tenant, normally the client identity, by
+/// prefixing its backend namespace with `<tenant>/`. Several clients can
+/// therefore share one persistent backend without colliding on group IDs.
+#[derive(Clone)]
 pub struct MLSStorage {
-    storage: MemoryStorage,
+    backend: Rc<dyn StorageBackend>,
+    tenant: String,
+    transaction: Rc<RefCell<Option<Transaction>>>,
+}
+
+/// Writes buffered by an open transaction. A `None` value is a deletion.
+type Transaction = HashMap<(String, Vec<u8>), Option<Vec<u8>>>;
+
+impl Default for MLSStorage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MLSStorage {
     pub fn new() -> Self {
+        Self::with_backend(MemoryBackend::default(), DEFAULT_TENANT)
+    }
+
+    /// Create a storage that delegates every record of `tenant` to the given
+    /// backend
+    pub fn with_backend(backend: impl StorageBackend + 'static, tenant: &str) -> Self {
+        Self::with_shared_backend(Rc::new(backend), tenant)
+    }
+
+    /// Create a storage around a backend that the caller keeps a handle to
+    pub fn with_shared_backend(backend: Rc<dyn StorageBackend>, tenant: &str) -> Self {
         Self {
-            storage: MemoryStorage::default(),
+            backend,
+            tenant: tenant.to_string(),
+            transaction: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// List every tenant that has records in the shared backend
+    pub fn tenants(&self) -> Result<Vec<String>> {
+        let mut tenants: Vec<String> = self
+            .backend
+            .entries()?
+            .into_iter()
+            .filter_map(|(namespace, _, _)| tenant_of(&namespace).map(str::to_string))
+            .collect();
+        tenants.sort();
+        tenants.dedup();
+        Ok(tenants)
+    }
+
+    /// Delete every record of `tenant` from the shared backend, returning
+    /// how many were removed
+    pub fn wipe_tenant(&self, tenant: &str) -> Result<u32> {
+        let mut removed = 0;
+        for (namespace, key, _) in self.backend.entries()? {
+            if tenant_of(&namespace) == Some(tenant) {
+                self.backend.delete(&namespace, &key)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn scoped(&self, namespace: &str) -> String {
+        format!("{}{}{}", self.tenant, TENANT_SEPARATOR, namespace)
+    }
+
+    /// Every record of this storage's tenant, with unscoped namespaces
+    fn own_entries(&self) -> Result<Vec<StorageRecord>> {
+        Ok(self
+            .backend
+            .entries()?
+            .into_iter()
+            .filter_map(|(namespace, key, value)| {
+                let (tenant, namespace) = namespace.rsplit_once(TENANT_SEPARATOR)?;
+                (tenant == self.tenant).then(|| (namespace.to_string(), key, value))
+            })
+            .collect())
+    }
+
+    /// Start buffering writes and deletes until `commit_transaction` or
+    /// `rollback_transaction`. Reads observe the buffered changes.
+    pub fn begin_transaction(&self) -> Result<()> {
+        let mut transaction = self.transaction.borrow_mut();
+        if transaction.is_some() {
+            return Err(Error::InvalidState(
+                "A storage transaction is already open".to_string(),
+            ));
+        }
+        *transaction = Some(Transaction::new());
+        Ok(())
+    }
+
+    /// Apply every buffered change to the backend
+    pub fn commit_transaction(&self) -> Result<()> {
+        let transaction = self
+            .transaction
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| Error::InvalidState("No storage transaction is open".to_string()))?;
+
+        for ((namespace, key), value) in transaction {
+            match value {
+                Some(value) => self.backend.put(&self.scoped(&namespace), &key, &value)?,
+                None => self.backend.delete(&self.scoped(&namespace), &key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard every buffered change
+    pub fn rollback_transaction(&self) {
+        self.transaction.borrow_mut().take();
+    }
+
+    /// Serialize every record of this tenant (group states, key packages,
+    /// signature keys, HPKE keys and PSKs) into a versioned binary snapshot.
+    /// Namespaces are stored unscoped, so a snapshot can be imported under
+    /// another tenant.
+    ///
+    /// Layout: magic, u16 version, u32 record count, then for each record a
+    /// u32 length-prefixed namespace, key and value. All integers are
+    /// big-endian and records are sorted by namespace and key.
+    pub fn export_snapshot(&self) -> Result<Vec<u8>> {
+        let mut records = self.own_entries()?;
+        records.sort();
+
+        let mut out = Vec::new();
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+        write_len(&mut out, records.len())?;
+        for (namespace, key, value) in &records {
+            write_bytes(&mut out, namespace.as_bytes())?;
+            write_bytes(&mut out, key)?;
+            write_bytes(&mut out, value)?;
+        }
+
+        Ok(out)
+    }
+
+    /// Replace the records of this tenant with a snapshot produced by
+    /// `export_snapshot`, or a version 1 snapshot of the openmls memory
+    /// storage. Nothing is changed if the snapshot is malformed.
+    pub fn import_snapshot(&self, snapshot: &[u8]) -> Result<()> {
+        let mut reader = SnapshotReader { data: snapshot };
+
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(Error::StorageError("Not a storage snapshot".to_string()));
+        }
+
+        let version = reader.take(2)?;
+        let version = u16::from_be_bytes([version[0], version[1]]);
+        let read_record = match version {
+            SNAPSHOT_VERSION => read_record,
+            SNAPSHOT_VERSION_V1 => read_v1_record,
+            _ => {
+                return Err(Error::UnsupportedStorageVersion {
+                    namespace: "snapshot".to_string(),
+                    version,
+                })
+            }
+        };
+
+        // The count is untrusted, so records are not preallocated for it
+        let count = reader.read_len()?;
+        let mut records = Vec::new();
+        for _ in 0..count {
+            records.push(read_record(&mut reader)?);
+        }
+
+        if !reader.data.is_empty() {
+            return Err(Error::StorageError(
+                "Trailing bytes after snapshot records".to_string(),
+            ));
+        }
+
+        // Buffer the wipe and the writes so a failure leaves the tenant as is
+        self.begin_transaction()?;
+        match self.replace_own_entries(records) {
+            Ok(()) => self.commit_transaction(),
+            Err(e) => {
+                self.rollback_transaction();
+                Err(e)
+            }
+        }
+    }
+
+    fn replace_own_entries(&self, records: Vec<StorageRecord>) -> Result<()> {
+        for (namespace, key, _) in self.own_entries()? {
+            self.delete_raw(&namespace, &key)?;
+        }
+        for (namespace, key, value) in records {
+            self.put_raw(&namespace, &key, &value)?;
+        }
+        Ok(())
+    }
+
+    /// Raw records of the key packages that no Welcome has consumed yet
+    pub fn unused_key_package_records(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let records = self.own_entries()?;
+        let consumed: HashSet<&Vec<u8>> = records
+            .iter()
+            .filter(|(namespace, _, _)| namespace == CONSUMED_KEY_PACKAGE_NAMESPACE)
+            .map(|(_, key, _)| key)
+            .collect();
+
+        Ok(records
+            .iter()
+            .filter(|(namespace, key, _)| namespace == KEY_PACKAGE_NAMESPACE && !consumed.contains(key))
+            .map(|(_, key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    /// Number of key packages no Welcome has consumed yet, not counting
+    /// last-resort packages
+    pub fn unused_key_package_count(&self) -> Result<u32> {
+        let last_resort: HashSet<Vec<u8>> = self.last_resort_key_package_keys()?.into_iter().collect();
+        let unused = self
+            .unused_key_package_records()?
+            .into_iter()
+            .filter(|(key, _)| !last_resort.contains(key))
+            .count();
+        Ok(unused as u32)
+    }
+
+    /// Raw keys of the key packages flagged as last resort
+    pub fn last_resort_key_package_keys(&self) -> Result<Vec<Vec<u8>>> {
+        Ok(self
+            .own_entries()?
+            .into_iter()
+            .filter(|(namespace, _, _)| namespace == LAST_RESORT_KEY_PACKAGE_NAMESPACE)
+            .map(|(_, key, _)| key)
+            .collect())
+    }
+
+    /// Store raw key package records, e.g. ones recovered from a backup,
+    /// flagging those keyed in `last_resort`
+    pub fn import_key_package_records(
+        &self,
+        records: &[(Vec<u8>, Vec<u8>)],
+        last_resort: &[Vec<u8>],
+    ) -> Result<()> {
+        for (key, value) in records {
+            self.put_raw(KEY_PACKAGE_NAMESPACE, key, value)?;
+        }
+        for key in last_resort {
+            self.put_raw(LAST_RESORT_KEY_PACKAGE_NAMESPACE, key, &encode_record(&encode(&())?))?;
+        }
+        Ok(())
+    }
+
+    /// Flag the key package with `hash_ref` as last resort. Welcomes never
+    /// consume it, so it stays available once the other packages run out.
+    pub fn mark_key_package_last_resort<K: Serialize>(&self, hash_ref: &K) -> Result<()> {
+        self.write(LAST_RESORT_KEY_PACKAGE_NAMESPACE, hash_ref, &())
+    }
+
+    /// Record that the user verified `fingerprint` for the member `identity`
+    pub fn write_verified_fingerprint(&self, identity: &[u8], fingerprint: &[u8]) -> Result<()> {
+        self.write(VERIFIED_KEY_NAMESPACE, identity, fingerprint)
+    }
+
+    /// The fingerprint last verified for the member `identity`, if any
+    pub fn read_verified_fingerprint(&self, identity: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.read(VERIFIED_KEY_NAMESPACE, identity)
+    }
+
+    /// IDs of every group held by this storage's tenant
+    pub fn group_ids<G: DeserializeOwned>(&self) -> Result<Vec<G>> {
+        self.own_entries()?
+            .into_iter()
+            .filter(|(namespace, _, _)| namespace == GROUP_STATE_NAMESPACE)
+            .map(|(_, key, _)| {
+                serde_json::from_slice(&key).map_err(|e| Error::StorageError(e.to_string()))
+            })
+            .collect()
+    }
+
+    /// The hash references among `hash_refs` of key packages this storage
+    /// holds that a Welcome would consume, i.e. all but last-resort ones.
+    /// Joining deletes the packages, so ask before the join.
+    pub fn consumable_key_packages<K: Serialize + Clone>(&self, hash_refs: &[K]) -> Result<Vec<K>> {
+        let mut consumable = Vec::new();
+        for hash_ref in hash_refs {
+            let key = encode(hash_ref)?;
+            if self.get_raw(KEY_PACKAGE_NAMESPACE, &key)?.is_some()
+                && self.get_raw(LAST_RESORT_KEY_PACKAGE_NAMESPACE, &key)?.is_none()
+            {
+                consumable.push(hash_ref.clone());
+            }
+        }
+        Ok(consumable)
+    }
+
+    /// Remember that the key packages with the given hash references were
+    /// used to join a group, so that `compact` can drop them and backups
+    /// leave them out
+    pub fn mark_key_packages_consumed<K: Serialize>(&self, hash_refs: &[K]) -> Result<()> {
+        for hash_ref in hash_refs {
+            self.write(CONSUMED_KEY_PACKAGE_NAMESPACE, hash_ref, &())?;
+        }
+        Ok(())
+    }
+
+    /// Drop encryption key pairs of epochs more than `retain_epochs` behind
+    /// the newest stored epoch of their group, and key packages that were
+    /// consumed by a Welcome.
+    pub fn compact(&self, retain_epochs: u64) -> Result<CompactionStats> {
+        let records = self.own_entries()?;
+        let mut stats = CompactionStats::default();
+
+        // Epoch key pairs are keyed by (group id, epoch, leaf index)
+        let mut epoch_keys = Vec::new();
+        let mut newest_epochs: HashMap<String, u64> = HashMap::new();
+        for (namespace, key, value) in &records {
+            if namespace != EPOCH_KEY_PAIRS_NAMESPACE {
+                continue;
+            }
+            let (group_id, epoch, _leaf_index): (serde_json::Value, u64, u32) =
+                serde_json::from_slice(key).map_err(|e| Error::StorageError(e.to_string()))?;
+            let group_id = group_id.to_string();
+            let newest = newest_epochs.entry(group_id.clone()).or_insert(epoch);
+            *newest = (*newest).max(epoch);
+            epoch_keys.push((group_id, epoch, key, value.len()));
+        }
+
+        for (group_id, epoch, key, value_len) in epoch_keys {
+            if epoch + retain_epochs < newest_epochs[&group_id] {
+                self.delete_raw(EPOCH_KEY_PAIRS_NAMESPACE, key)?;
+                stats.record(key.len() + value_len);
+            }
+        }
+
+        let consumed: HashSet<&Vec<u8>> = records
+            .iter()
+            .filter(|(namespace, _, _)| namespace == CONSUMED_KEY_PACKAGE_NAMESPACE)
+            .map(|(_, key, _)| key)
+            .collect();
+        for (namespace, key, value) in &records {
+            if namespace == KEY_PACKAGE_NAMESPACE && consumed.contains(key) {
+                self.delete_raw(KEY_PACKAGE_NAMESPACE, key)?;
+                stats.record(key.len() + value.len());
+            }
+        }
+        for key in consumed {
+            self.delete_raw(CONSUMED_KEY_PACKAGE_NAMESPACE, key)?;
+            stats.record(key.len());
+        }
+
+        Ok(stats)
+    }
+
+    fn get_raw(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(transaction) = self.transaction.borrow().as_ref() {
+            if let Some(value) = transaction.get(&(namespace.to_string(), key.to_vec())) {
+                return Ok(value.clone());
+            }
+        }
+        self.backend.get(&self.scoped(namespace), key)
+    }
+
+    fn put_raw(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        if let Some(transaction) = self.transaction.borrow_mut().as_mut() {
+            transaction.insert((namespace.to_string(), key.to_vec()), Some(value.to_vec()));
+            return Ok(());
+        }
+        self.backend.put(&self.scoped(namespace), key, value)
+    }
+
+    fn delete_raw(&self, namespace: &str, key: &[u8]) -> Result<()> {
+        if let Some(transaction) = self.transaction.borrow_mut().as_mut() {
+            transaction.insert((namespace.to_string(), key.to_vec()), None);
+            return Ok(());
+        }
+        self.backend.delete(&self.scoped(namespace), key)
+    }
+
+    fn write<K: Serialize + ?Sized, V: Serialize + ?Sized>(
+        &self,
+        namespace: &str,
+        key: &K,
+        value: &V,
+    ) -> Result<()> {
+        let key = encode(key)?;
+        let value = encode(value)?;
+        self.put_raw(namespace, &key, &encode_record(&value))
+    }
+
+    fn read<K: Serialize + ?Sized, V: DeserializeOwned>(
+        &self,
+        namespace: &str,
+        key: &K,
+    ) -> Result<Option<V>> {
+        let key = encode(key)?;
+        let record = match self.get_raw(namespace, &key)? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        let (value, migrated) = decode_record(namespace, &record)?;
+        if migrated {
+            self.put_raw(namespace, &key, &encode_record(&value))?;
+        }
+
+        serde_json::from_slice(&value)
+            .map(Some)
+            .map_err(|e| Error::StorageError(e.to_string()))
+    }
+
+    fn delete<K: Serialize + ?Sized>(&self, namespace: &str, key: &K) -> Result<()> {
+        let key = encode(key)?;
+        self.delete_raw(namespace, &key)
+    }
+}
+
+/// Totals of what a `compact` run removed
+#[derive(Default, Debug, Clone, Copy)]
+pub struct CompactionStats {
+    pub records_removed: u32,
+    pub bytes_removed: u64,
+}
+
+impl CompactionStats {
+    fn record(&mut self, bytes: usize) {
+        self.records_removed += 1;
+        self.bytes_removed += bytes as u64;
+    }
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| Error::StorageError(e.to_string()))
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) -> Result<()> {
+    let len = u32::try_from(len)
+        .map_err(|_| Error::StorageError("Snapshot record too large".to_string()))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    Ok(())
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> Result<()> {
+    write_len(out, bytes.len())?;
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Read a record of the current snapshot layout
+fn read_record(reader: &mut SnapshotReader) -> Result<StorageRecord> {
+    let namespace = String::from_utf8(reader.read_bytes()?.to_vec())
+        .map_err(|e| Error::StorageError(e.to_string()))?;
+    let key = reader.read_bytes()?.to_vec();
+    let value = reader.read_bytes()?.to_vec();
+    Ok((namespace, key, value))
+}
+
+/// Read a version 1 record: an openmls memory storage key (label, JSON key
+/// and u16 storage version) and a bare JSON value. Bare values are migrated
+/// by `decode_record` when first read.
+fn read_v1_record(reader: &mut SnapshotReader) -> Result<StorageRecord> {
+    let storage_key = reader.read_bytes()?;
+    let value = reader.read_bytes()?.to_vec();
+
+    let key = storage_key
+        .len()
+        .checked_sub(2)
+        .map(|len| &storage_key[..len])
+        .ok_or_else(|| Error::StorageError("Truncated storage snapshot".to_string()))?;
+    let (label, namespace) = V1_LABELS
+        .iter()
+        .find(|(label, _)| key.starts_with(label))
+        .ok_or_else(|| Error::StorageError("Unknown record in version 1 snapshot".to_string()))?;
+    let key = &key[label.len()..];
+
+    // Epoch key pairs were keyed by their JSON parts back to back, now by
+    // a JSON tuple of them
+    if *namespace != EPOCH_KEY_PAIRS_NAMESPACE {
+        return Ok((namespace.to_string(), key.to_vec(), value));
+    }
+
+    let mut parts = Vec::new();
+    let mut stream = serde_json::Deserializer::from_slice(key).into_iter::<serde::de::IgnoredAny>();
+    let mut start = 0;
+    while let Some(part) = stream.next() {
+        part.map_err(|e| Error::StorageError(e.to_string()))?;
+        parts.push(&key[start..stream.byte_offset()]);
+        start = stream.byte_offset();
+    }
+    let key = [&b"["[..], &parts.join(&b','), &b"]"[..]].concat();
+    Ok((namespace.to_string(), key, value))
+}
+
+/// Cursor over the bytes of a storage snapshot
+struct SnapshotReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(Error::StorageError("Truncated storage snapshot".to_string()));
         }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn read_len(&mut self) -> Result<usize> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_len()?;
+        self.take(len)
     }
 }
 
 impl StorageProvider<CURRENT_VERSION> for MLSStorage {
-    type Error = <MemoryStorage as StorageProvider<CURRENT_VERSION>>::Error;
+    type Error = Error;
 
     fn write_mls_group_state<
         GroupId: openmls_traits::types::GroupId<CURRENT_VERSION>,
@@ -27,8 +672,8 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
         &self,
         group_id: &GroupId,
         group_state: &MlsGroupState,
-    ) -> Result<(), Self::Error> {
-        self.storage.write_mls_group_state(group_id, group_state)
+    ) -> std::result::Result<(), Self::Error> {
+        self.write(GROUP_STATE_NAMESPACE, group_id, group_state)
     }
 
     fn read_mls_group_state<
@@ -37,15 +682,15 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
     >(
         &self,
         group_id: &GroupId,
-    ) -> Result<Option<MlsGroupState>, Self::Error> {
-        self.storage.read_mls_group_state(group_id)
+    ) -> std::result::Result<Option<MlsGroupState>, Self::Error> {
+        self.read(GROUP_STATE_NAMESPACE, group_id)
     }
 
     fn delete_mls_group_state<GroupId: openmls_traits::types::GroupId<CURRENT_VERSION>>(
         &self,
         group_id: &GroupId,
-    ) -> Result<(), Self::Error> {
-        self.storage.delete_mls_group_state(group_id)
+    ) -> std::result::Result<(), Self::Error> {
+        self.delete(GROUP_STATE_NAMESPACE, group_id)
     }
 
     fn write_key_package<
@@ -55,8 +700,8 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
         &self,
         hash_ref: &HashReference,
         key_package: &KeyPackage,
-    ) -> Result<(), Self::Error> {
-        self.storage.write_key_package(hash_ref, key_package)
+    ) -> std::result::Result<(), Self::Error> {
+        self.write(KEY_PACKAGE_NAMESPACE, hash_ref, key_package)
     }
 
     fn read_key_package<
@@ -65,15 +710,15 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
     >(
         &self,
         hash_ref: &HashReference,
-    ) -> Result<Option<KeyPackage>, Self::Error> {
-        self.storage.read_key_package(hash_ref)
+    ) -> std::result::Result<Option<KeyPackage>, Self::Error> {
+        self.read(KEY_PACKAGE_NAMESPACE, hash_ref)
     }
 
     fn delete_key_package<HashReference: openmls_traits::types::HashReference<CURRENT_VERSION>>(
         &self,
         hash_ref: &HashReference,
-    ) -> Result<(), Self::Error> {
-        self.storage.delete_key_package(hash_ref)
+    ) -> std::result::Result<(), Self::Error> {
+        self.delete(KEY_PACKAGE_NAMESPACE, hash_ref)
     }
 
     fn write_signature_key_pair<
@@ -83,9 +728,8 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
         &self,
         public_key: &SignaturePublicKey,
         signature_key_pair: &SignatureKeyPair,
-    ) -> Result<(), Self::Error> {
-        self.storage
-            .write_signature_key_pair(public_key, signature_key_pair)
+    ) -> std::result::Result<(), Self::Error> {
+        self.write(SIGNATURE_KEY_NAMESPACE, public_key, signature_key_pair)
     }
 
     fn read_signature_key_pair<
@@ -94,8 +738,17 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
     >(
         &self,
         public_key: &SignaturePublicKey,
-    ) -> Result<Option<SignatureKeyPair>, Self::Error> {
-        self.storage.read_signature_key_pair(public_key)
+    ) -> std::result::Result<Option<SignatureKeyPair>, Self::Error> {
+        self.read(SIGNATURE_KEY_NAMESPACE, public_key)
+    }
+
+    fn delete_signature_key_pair<
+        SignaturePublicKey: openmls_traits::types::SignaturePublicKey<CURRENT_VERSION>,
+    >(
+        &self,
+        public_key: &SignaturePublicKey,
+    ) -> std::result::Result<(), Self::Error> {
+        self.delete(SIGNATURE_KEY_NAMESPACE, public_key)
     }
 
     fn write_encryption_key_pair<
@@ -105,9 +758,8 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
         &self,
         public_key: &HpkePublicKey,
         encryption_key_pair: &HpkeKeyPair,
-    ) -> Result<(), Self::Error> {
-        self.storage
-            .write_encryption_key_pair(public_key, encryption_key_pair)
+    ) -> std::result::Result<(), Self::Error> {
+        self.write(ENCRYPTION_KEY_NAMESPACE, public_key, encryption_key_pair)
     }
 
     fn read_encryption_key_pair<
@@ -116,8 +768,8 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
     >(
         &self,
         public_key: &HpkePublicKey,
-    ) -> Result<Option<HpkeKeyPair>, Self::Error> {
-        self.storage.read_encryption_key_pair(public_key)
+    ) -> std::result::Result<Option<HpkeKeyPair>, Self::Error> {
+        self.read(ENCRYPTION_KEY_NAMESPACE, public_key)
     }
 
     fn write_encryption_epoch_key_pairs<
@@ -130,9 +782,12 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
         epoch: &EpochKey,
         leaf_index: u32,
         key_pairs: &[HpkeKeyPair],
-    ) -> Result<(), Self::Error> {
-        self.storage
-            .write_encryption_epoch_key_pairs(group_id, epoch, leaf_index, key_pairs)
+    ) -> std::result::Result<(), Self::Error> {
+        self.write(
+            EPOCH_KEY_PAIRS_NAMESPACE,
+            &(group_id, epoch, leaf_index),
+            &key_pairs,
+        )
     }
 
     fn read_encryption_epoch_key_pairs<
@@ -144,9 +799,10 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
         group_id: &GroupId,
         epoch: &EpochKey,
         leaf_index: u32,
-    ) -> Result<Vec<HpkeKeyPair>, Self::Error> {
-        self.storage
-            .read_encryption_epoch_key_pairs(group_id, epoch, leaf_index)
+    ) -> std::result::Result<Vec<HpkeKeyPair>, Self::Error> {
+        Ok(self
+            .read(EPOCH_KEY_PAIRS_NAMESPACE, &(group_id, epoch, leaf_index))?
+            .unwrap_or_default())
     }
 
     fn delete_encryption_epoch_key_pairs<
@@ -157,23 +813,50 @@ impl StorageProvider<CURRENT_VERSION> for MLSStorage {
         group_id: &GroupId,
         epoch: &EpochKey,
         leaf_index: u32,
-    ) -> Result<(), Self::Error> {
-        self.storage
-            .delete_encryption_epoch_key_pairs(group_id, epoch, leaf_index)
+    ) -> std::result::Result<(), Self::Error> {
+        self.delete(EPOCH_KEY_PAIRS_NAMESPACE, &(group_id, epoch, leaf_index))
     }
 
     fn write_psk<PskId: openmls_traits::types::PskId<CURRENT_VERSION>>(
         &self,
         psk_id: &PskId,
         psk: &openmls_traits::storage::PskBundle<CURRENT_VERSION>,
-    ) -> Result<(), Self::Error> {
-        self.storage.write_psk(psk_id, psk)
+    ) -> std::result::Result<(), Self::Error> {
+        self.write(PSK_NAMESPACE, psk_id, psk)
     }
 
     fn read_psk<PskId: openmls_traits::types::PskId<CURRENT_VERSION>>(
         &self,
         psk_id: &PskId,
-    ) -> Result<Option<openmls_traits::storage::PskBundle<CURRENT_VERSION>>, Self::Error> {
-        self.storage.read_psk(psk_id)
+    ) -> std::result::Result<Option<openmls_traits::storage::PskBundle<CURRENT_VERSION>>, Self::Error> {
+        self.read(PSK_NAMESPACE, psk_id)
+    }
+
+    fn write_message_secrets<
+        GroupId: openmls_traits::types::GroupId<CURRENT_VERSION>,
+        MessageSecrets: openmls_traits::types::MessageSecrets<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+        message_secrets: &MessageSecrets,
+    ) -> std::result::Result<(), Self::Error> {
+        self.write(MESSAGE_SECRETS_NAMESPACE, group_id, message_secrets)
+    }
+
+    fn read_message_secrets<
+        GroupId: openmls_traits::types::GroupId<CURRENT_VERSION>,
+        MessageSecrets: openmls_traits::types::MessageSecrets<CURRENT_VERSION>,
+    >(
+        &self,
+        group_id: &GroupId,
+    ) -> std::result::Result<Option<MessageSecrets>, Self::Error> {
+        self.read(MESSAGE_SECRETS_NAMESPACE, group_id)
+    }
+
+    fn delete_message_secrets<GroupId: openmls_traits::types::GroupId<CURRENT_VERSION>>(
+        &self,
+        group_id: &GroupId,
+    ) -> std::result::Result<(), Self::Error> {
+        self.delete(MESSAGE_SECRETS_NAMESPACE, group_id)
     }
 }
\ No newline at end of file
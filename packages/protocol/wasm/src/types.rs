@@ -24,6 +24,142 @@ impl MLSCommit {
     }
 }
 
+/// The result of a batch add or remove: one commit (and for adds one
+/// Welcome) covering every accepted item, or none if no item was accepted,
+/// and what happened to each item in input order
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchCommit {
+    pub commit: Option<MLSCommit>,
+    pub items: Vec<BatchItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItem {
+    /// Position of the item in the input
+    pub index: u32,
+    /// The member's identity, if it could be determined
+    pub member: Option<String>,
+    pub status: BatchStatus,
+    /// Why the item was skipped
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchStatus {
+    Added,
+    Removed,
+    /// Already a member, or repeated earlier in the same batch
+    Duplicate,
+    /// No such member in the group
+    Unknown,
+    /// Malformed, or refused by the group's checks
+    Rejected,
+}
+
+/// A commit produced for one group by an operation spanning several groups
+#[wasm_bindgen]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupCommit {
+    pub(crate) group_id: Vec<u8>,
+    pub(crate) commit: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl GroupCommit {
+    #[wasm_bindgen(getter, js_name = groupId)]
+    pub fn group_id(&self) -> Vec<u8> {
+        self.group_id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn commit(&self) -> Vec<u8> {
+        self.commit.clone()
+    }
+}
+
+/// The devices one user has in a group. A `None` device is a member whose
+/// identity predates device IDs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserDevices {
+    pub user_id: String,
+    pub display_name: Option<String>,
+    pub devices: Vec<Option<String>>,
+}
+
+/// Fingerprint of one group member and how it compares to the fingerprint
+/// the user last verified for that identity
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberFingerprint {
+    pub identity: String,
+    pub display_name: Option<String>,
+    /// Six groups of five digits for reading out loud
+    pub numeric: String,
+    /// Bytes to render as a QR code for scanning
+    pub qr_payload: Vec<u8>,
+    /// The current key is the one the user verified
+    pub verified: bool,
+    /// The user verified a different key for this identity before
+    pub key_changed: bool,
+}
+
+/// What a key package claims about its owner, after its signature has been
+/// verified. `userId`, `deviceId` and `displayName` are absent if the
+/// credential does not carry a structured identity.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPackageInfo {
+    pub identity: String,
+    pub user_id: Option<String>,
+    pub device_id: Option<String>,
+    pub display_name: Option<String>,
+    pub credential_type: String,
+    pub signature_key: Vec<u8>,
+    /// Fingerprint the owner will show once admitted, as in `MemberFingerprint`
+    pub fingerprint: String,
+    pub ciphersuite: String,
+    pub capabilities: KeyPackageCapabilities,
+    /// Extension types of the key package and of its leaf node
+    pub extensions: Vec<u16>,
+    pub leaf_extensions: Vec<u16>,
+    pub last_resort: bool,
+    pub not_before: u64,
+    pub not_after: u64,
+    /// The lifetime covers the current time, allowing for clock skew
+    pub lifetime_valid: bool,
+    /// Why the credential policy would reject the owner, if it would
+    pub trust_error: Option<String>,
+    /// The credential was checked against a trust anchor or the attestation
+    /// key, rather than accepted unverified because neither is configured
+    pub verified: bool,
+}
+
+/// What every current member of a group supports: application features and
+/// MLS extension types, next to the features the group requires
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupCapabilities {
+    pub features: Vec<String>,
+    pub extensions: Vec<u16>,
+    pub required: Vec<String>,
+}
+
+/// Protocol features a key package's leaf node supports, as registry values
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPackageCapabilities {
+    pub versions: Vec<u16>,
+    pub ciphersuites: Vec<u16>,
+    pub extensions: Vec<u16>,
+    pub proposals: Vec<u16>,
+    pub credentials: Vec<u16>,
+}
+
 #[wasm_bindgen]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MLSCiphertext {
@@ -49,6 +185,26 @@ impl MLSCiphertext {
     }
 }
 
+#[wasm_bindgen]
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct CompactionReport {
+    pub(crate) records_removed: u32,
+    pub(crate) bytes_removed: u64,
+}
+
+#[wasm_bindgen]
+impl CompactionReport {
+    #[wasm_bindgen(getter, js_name = recordsRemoved)]
+    pub fn records_removed(&self) -> u32 {
+        self.records_removed
+    }
+
+    #[wasm_bindgen(getter, js_name = bytesRemoved)]
+    pub fn bytes_removed(&self) -> u64 {
+        self.bytes_removed
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GroupInfo {
     pub id: String,
@@ -0,0 +1,79 @@
+//! Per-message overhead of group operations, run with
+//! `wasm-pack test --headless --chrome --release -- --nocapture`.
+//!
+//! "reload" reopens the group from storage before every message, which is
+//! what every `MLSGroup` call used to do; "cached" reuses the live group.
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::*;
+    use opencall_mls::{MLSClient, MLSGroup};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const ITERATIONS: u32 = 200;
+
+    fn time_per_message(mut op: impl FnMut()) -> f64 {
+        let start = js_sys::Date::now();
+        for _ in 0..ITERATIONS {
+            op();
+        }
+        (js_sys::Date::now() - start) / f64::from(ITERATIONS)
+    }
+
+    fn setup(group_id: &[u8]) -> (MLSClient, MLSGroup) {
+        let client = MLSClient::new("bench_user".to_string()).unwrap();
+        let group = client.create_group(group_id.to_vec()).unwrap();
+        (client, group)
+    }
+
+    #[wasm_bindgen_test]
+    fn bench_encrypt_reload_per_call() {
+        let group_id = vec![101, 1];
+        let (client, _group) = setup(&group_id);
+        let plaintext = [0u8; 256];
+
+        let ms = time_per_message(|| {
+            let group = client.load_group(group_id.clone()).unwrap();
+            group.encrypt_message(&plaintext).unwrap();
+        });
+
+        console_log!("encrypt (reload per call): {:.3} ms/message", ms);
+    }
+
+    #[wasm_bindgen_test]
+    fn bench_encrypt_cached() {
+        let (_client, group) = setup(&[101, 2]);
+        let plaintext = [0u8; 256];
+
+        let ms = time_per_message(|| {
+            group.encrypt_message(&plaintext).unwrap();
+        });
+
+        console_log!("encrypt (cached group): {:.3} ms/message", ms);
+    }
+
+    #[wasm_bindgen_test]
+    fn bench_epoch_reload_per_call() {
+        let group_id = vec![101, 3];
+        let (client, _group) = setup(&group_id);
+
+        let ms = time_per_message(|| {
+            let group = client.load_group(group_id.clone()).unwrap();
+            group.get_current_epoch().unwrap();
+        });
+
+        console_log!("epoch lookup (reload per call): {:.3} ms/call", ms);
+    }
+
+    #[wasm_bindgen_test]
+    fn bench_epoch_cached() {
+        let (_client, group) = setup(&[101, 4]);
+
+        let ms = time_per_message(|| {
+            group.get_current_epoch().unwrap();
+        });
+
+        console_log!("epoch lookup (cached group): {:.3} ms/call", ms);
+    }
+}
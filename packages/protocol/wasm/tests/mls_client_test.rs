@@ -1,7 +1,10 @@
 #[cfg(test)]
 mod tests {
     use wasm_bindgen_test::*;
-    use opencall_mls::MLSClient;
+    use opencall_mls::{BatchCommit, BatchStatus, Clock, CredentialIssuer, CredentialValidator, GroupCapabilities, GroupCommit, Identity, KeyPackageInfo, MLSClient, MemberFingerprint, UserDevices};
+    use openmls::prelude::Credential;
+    use wasm_bindgen::JsValue;
+    use wasm_bindgen_futures::JsFuture;
 
     wasm_bindgen_test_configure!(run_in_browser);
 
@@ -98,4 +101,1321 @@ mod tests {
         let remove_result = group.remove_member("non_existent_user");
         assert!(remove_result.is_err());
     }
-}
\ No newline at end of file
+
+    #[wasm_bindgen_test]
+    fn test_restore_preserves_identity() {
+        let client = MLSClient::new("test_user".to_string()).unwrap();
+        let state = client.export_state().unwrap();
+        
+        let restored = MLSClient::restore("test_user".to_string(), &state).unwrap();
+        assert_eq!(restored.signature_public_key(), client.signature_public_key());
+        
+        // State cannot be restored under another identity
+        let other = MLSClient::restore("other_user".to_string(), &state);
+        assert!(other.is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_storage_snapshot_roundtrip() {
+        let client = MLSClient::new("test_user".to_string()).unwrap();
+        let group_id = vec![25, 26, 27, 28];
+        let group = client.create_group(group_id.clone()).unwrap();
+        group.encrypt_message(b"before snapshot").unwrap();
+        
+        let state = client.export_state().unwrap();
+        let snapshot = client.export_storage().unwrap();
+        
+        // A restored client picks the group back up from the snapshot
+        let restored = MLSClient::restore("test_user".to_string(), &state).unwrap();
+        assert!(restored.load_group(group_id.clone()).is_err());
+        restored.import_storage(&snapshot).unwrap();
+        
+        let group = restored.load_group(group_id).unwrap();
+        assert_eq!(group.get_current_epoch().unwrap(), 0);
+        assert!(group.encrypt_message(b"after snapshot").is_ok());
+        
+        // Truncated snapshots are rejected
+        assert!(restored.import_storage(&snapshot[..snapshot.len() - 1]).is_err());
+    }
+    
+    /// The `(namespace, key, value)` records of a snapshot from `exportStorage`
+    fn snapshot_records(snapshot: &[u8]) -> Vec<(String, Vec<u8>, Vec<u8>)> {
+        let mut data = &snapshot[10..];
+        let mut read = || {
+            let len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+            let bytes = data[4..4 + len].to_vec();
+            data = &data[4 + len..];
+            bytes
+        };
+        (0..u32::from_be_bytes(snapshot[6..10].try_into().unwrap()))
+            .map(|_| (String::from_utf8(read()).unwrap(), read(), read()))
+            .collect()
+    }
+    
+    /// Rewrite a current snapshot in the version 1 layout of the openmls
+    /// memory storage: labelled keys and bare JSON values
+    fn to_v1_snapshot(snapshot: &[u8]) -> Vec<u8> {
+        let labels = [
+            ("group_state", "GroupState"),
+            ("key_package", "KeyPackage"),
+            ("signature_key_pair", "SignatureKeyPair"),
+            ("encryption_key_pair", "EncryptionKeyPair"),
+            ("epoch_key_pairs", "EpochKeyPairs"),
+            ("psk", "Psk"),
+        ];
+        let mut records = Vec::new();
+        for (namespace, key, value) in snapshot_records(snapshot) {
+            let label = labels.iter().find(|(name, _)| *name == namespace).unwrap().1;
+            let key = if namespace == "epoch_key_pairs" {
+                let parts: Vec<serde_json::Value> = serde_json::from_slice(&key).unwrap();
+                parts.iter().flat_map(|part| serde_json::to_vec(part).unwrap()).collect()
+            } else {
+                key
+            };
+            records.push(([label.as_bytes(), &key, &1u16.to_be_bytes()].concat(), value[3..].to_vec()));
+        }
+        
+        let mut out = b"OCMS".to_vec();
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&(records.len() as u32).to_be_bytes());
+        for (key, value) in records {
+            out.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            out.extend_from_slice(&key);
+            out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            out.extend_from_slice(&value);
+        }
+        out
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_import_v1_snapshot() {
+        let client = MLSClient::new("test_user".to_string()).unwrap();
+        let group_id = vec![163, 164, 165, 166];
+        client.create_group(group_id.clone()).unwrap();
+        let state = client.export_state().unwrap();
+        let snapshot = to_v1_snapshot(&client.export_storage().unwrap());
+        
+        let restored = MLSClient::restore("test_user".to_string(), &state).unwrap();
+        restored.import_storage(&snapshot).unwrap();
+        let group = restored.load_group(group_id.clone()).unwrap();
+        assert_eq!(group.get_current_epoch().unwrap(), 0);
+        assert!(group.encrypt_message(b"after migration").is_ok());
+        
+        // Migrated records are exported in the current layout
+        let reexported = restored.export_storage().unwrap();
+        assert_eq!(&reexported[4..6], &2u16.to_be_bytes());
+        
+        // A failed import leaves the previous records in place
+        let mut bad = snapshot.clone();
+        bad[6..10].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(restored.import_storage(&bad).is_err());
+        assert!(restored.load_group(group_id).is_ok());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_flush_and_hydrate() {
+        let client = MLSClient::new("test_user".to_string()).unwrap();
+        let group_id = vec![29, 30, 31, 32];
+        client.create_group(group_id.clone()).unwrap();
+        
+        let records = JsFuture::from(client.flush(None).unwrap()).await.unwrap();
+        assert!(js_sys::Array::from(&records).length() > 0);
+        
+        // Nothing changed since the last flush
+        let empty = JsFuture::from(client.flush(None).unwrap()).await.unwrap();
+        assert_eq!(js_sys::Array::from(&empty).length(), 0);
+        
+        // The flushed records are enough to bring the group back
+        let state = client.export_state().unwrap();
+        let hydrated = MLSClient::hydrate("test_user".to_string(), &state, records.clone(), None).unwrap();
+        let group = hydrated.load_group(group_id.clone()).unwrap();
+        assert_eq!(group.get_current_epoch().unwrap(), 0);
+        
+        // Records flushed under one tenant are not visible to another
+        let other = MLSClient::hydrate("test_user".to_string(), &state, records, Some("other".to_string())).unwrap();
+        assert!(other.load_group(group_id).is_err());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_js_storage_backend() {
+        let store = memory_store();
+        let client = MLSClient::with_storage("user1".to_string(), store.clone(), None, JsValue::UNDEFINED).unwrap();
+        let group_id = vec![179, 180, 181, 182];
+        
+        // Puts reach the JS store and gets read them back
+        let before = stored_records(&store).len();
+        client.create_group(group_id.clone()).unwrap();
+        assert!(stored_records(&store).len() > before);
+        let restored =
+            MLSClient::restore_with_storage("user1".to_string(), &client.export_state().unwrap(), store.clone(), None)
+                .unwrap();
+        assert!(restored.load_group(group_id.clone()).is_ok());
+        
+        // A missing key reads as absent rather than failing the store
+        let error = restored.load_group(vec![183, 184, 185, 186]).err().unwrap();
+        assert!(error.to_string().contains("Group not found"), "{}", error);
+        
+        // Errors thrown by the store surface as storage errors
+        fail_puts_after(&store, 0);
+        let error = client.create_group(vec![187, 188, 189, 190]).err().unwrap();
+        assert!(error.to_string().contains("Storage is full"), "{}", error);
+        js_sys::Reflect::set(&store, &"failAfter".into(), &JsValue::NULL).unwrap();
+        
+        // Deletes remove the records from the store
+        assert!(client.wipe_namespace("user1").unwrap() > 0);
+        assert!(stored_records(&store).is_empty());
+        assert!(restored.load_group(group_id).is_err());
+        
+        // The store must be an object with the required methods
+        assert!(MLSClient::with_storage("user1".to_string(), JsValue::from_str("store"), None, JsValue::UNDEFINED).is_err());
+        let incomplete = js_sys::JSON::parse(r#"{}"#).unwrap();
+        js_sys::Reflect::set(&incomplete, &"get".into(), &js_sys::Function::new_no_args("return null;")).unwrap();
+        let error = MLSClient::with_storage("user1".to_string(), incomplete, None, JsValue::UNDEFINED).err().unwrap();
+        assert!(error.to_string().contains("missing a `put` method"), "{}", error);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_storage_namespaces() {
+        let store = memory_store();
+        let alice = MLSClient::with_storage("alice".to_string(), store.clone(), None, JsValue::UNDEFINED).unwrap();
+        let bob = MLSClient::with_storage("bob".to_string(), store.clone(), None, JsValue::UNDEFINED).unwrap();
+        let work = MLSClient::with_storage(
+            "alice".to_string(),
+            store.clone(),
+            Some("alice-work".to_string()),
+            JsValue::UNDEFINED,
+        )
+        .unwrap();
+        
+        // The same group ID in every tenant does not collide
+        let group_id = vec![147, 148, 149, 150];
+        alice.create_group(group_id.clone()).unwrap();
+        bob.create_group(group_id.clone()).unwrap();
+        assert!(work.load_group(group_id.clone()).is_err());
+        assert_eq!(
+            alice.list_namespaces().unwrap(),
+            vec!["alice".to_string(), "alice-work".to_string(), "bob".to_string()]
+        );
+        
+        // Wiping one tenant leaves the others alone
+        assert!(alice.wipe_namespace("bob").unwrap() > 0);
+        assert!(bob.load_group(group_id.clone()).is_err());
+        assert!(alice.load_group(group_id).is_ok());
+        assert_eq!(alice.wipe_namespace("bob").unwrap(), 0);
+        assert_eq!(work.list_namespaces().unwrap(), vec!["alice".to_string(), "alice-work".to_string()]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_malformed_commit_leaves_group_unchanged() {
+        let client1 = MLSClient::new("user1".to_string()).unwrap();
+        let client2 = MLSClient::new("user2".to_string()).unwrap();
+        let group_id = vec![33, 34, 35, 36];
+        let group = client1.create_group(group_id.clone()).unwrap();
+        let _ = group.add_member(&client2.export_key_package().unwrap()).unwrap();
+        
+        let epoch = group.get_current_epoch().unwrap();
+        let members = group.get_member_count();
+        
+        // Garbage that does not even decode
+        assert!(group.process_commit(&[0xde, 0xad, 0xbe, 0xef]).is_err());
+        assert_eq!(group.get_current_epoch().unwrap(), epoch);
+        assert_eq!(group.get_member_count(), members);
+        
+        // A well-formed message that is not a commit this member can process
+        let other = client2.create_group(vec![37, 38, 39, 40]).unwrap();
+        let foreign = other.encrypt_message(b"not a commit").unwrap();
+        let foreign: opencall_mls::MLSCiphertext = serde_wasm_bindgen::from_value(foreign).unwrap();
+        assert!(group.process_commit(&foreign.data()).is_err());
+        assert_eq!(group.get_current_epoch().unwrap(), epoch);
+        assert_eq!(group.get_member_count(), members);
+        
+        // Both the live group and the stored state are still usable
+        assert!(group.encrypt_message(b"still works").is_ok());
+        let reloaded = client1.load_group(group_id).unwrap();
+        assert_eq!(reloaded.get_current_epoch().unwrap(), epoch);
+        assert_eq!(reloaded.get_member_count(), members);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_vetoed_commit_rolls_back() {
+        let client1 = MLSClient::new("user1".to_string()).unwrap();
+        let client2 = MLSClient::new("user2".to_string()).unwrap();
+        let client3 = MLSClient::new("user3".to_string()).unwrap();
+        let group_id = vec![139, 140, 141, 142];
+        let group1 = client1.create_group(group_id.clone()).unwrap();
+        let commit = group1.add_member(&client2.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        let group2 = client2.join_group(&commit.welcome()[0].to_vec()).unwrap();
+        
+        let epoch = group2.get_current_epoch().unwrap();
+        let snapshot = client2.export_storage().unwrap();
+        
+        // The commit decrypts and verifies, so openmls has staged it before
+        // the policy vetoes the new member
+        client2.set_credential_validator(DenyList(vec!["user3"]));
+        let commit = group1.add_member(&client3.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        let error = group2.process_commit(&commit.commit()).unwrap_err();
+        assert!(error.to_string().contains("Credential rejected"), "{}", error);
+        
+        assert_eq!(group2.get_current_epoch().unwrap(), epoch);
+        assert_eq!(group2.get_member_count(), 2);
+        assert_eq!(client2.export_storage().unwrap(), snapshot);
+        let reloaded = client2.load_group(group_id).unwrap();
+        assert_eq!(reloaded.get_current_epoch().unwrap(), epoch);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_configured_ciphersuite() {
+        let config = js_sys::JSON::parse(
+            r#"{ "ciphersuite": "MLS_128_DHKEMP256_AES128GCM_SHA256_P256" }"#,
+        )
+        .unwrap();
+        let p256_client = MLSClient::with_config("p256_user".to_string(), config).unwrap();
+        assert_eq!(p256_client.ciphersuite(), "MLS_128_DHKEMP256_AES128GCM_SHA256_P256");
+        assert!(p256_client.create_group(vec![41, 42, 43, 44]).is_ok());
+        
+        // A P-256 key package cannot join an X25519 group
+        let client = MLSClient::new("test_user".to_string()).unwrap();
+        let group = client.create_group(vec![45, 46, 47, 48]).unwrap();
+        let key_package = p256_client.export_key_package().unwrap();
+        let error = group.add_member(&key_package).unwrap_err();
+        assert!(error.to_string().contains("Ciphersuite mismatch"));
+        
+        // Unknown ciphersuites are rejected up front
+        let bad = js_sys::JSON::parse(r#"{ "ciphersuite": "NOPE" }"#).unwrap();
+        assert!(MLSClient::with_config("bad_user".to_string(), bad).is_err());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_hybrid_ciphersuite() {
+        let config = js_sys::JSON::parse(
+            r#"{ "ciphersuite": "MLS_256_XWING_CHACHA20POLY1305_SHA256_Ed25519" }"#,
+        )
+        .unwrap();
+        let pq_client = MLSClient::with_config("pq_user".to_string(), config.clone()).unwrap();
+        let pq_group = pq_client.create_group(vec![51, 52, 53, 54]).unwrap();
+        assert!(pq_group.is_post_quantum());
+        
+        // A second X-Wing client joins, and messages flow both ways
+        let pq_peer = MLSClient::with_config("pq_peer".to_string(), config.clone()).unwrap();
+        let commit = pq_group.add_member(&pq_peer.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        let peer_group = pq_peer.join_group(&commit.welcome()[0].to_vec()).unwrap();
+        assert!(peer_group.is_post_quantum());
+        
+        let message = pq_group.encrypt_message(b"Hello, quantum world!").unwrap();
+        let message: opencall_mls::MLSCiphertext = serde_wasm_bindgen::from_value(message).unwrap();
+        assert_eq!(peer_group.decrypt_message(&message.data()).unwrap(), b"Hello, quantum world!");
+        
+        // Commits by either member are processed by the other
+        let third = MLSClient::with_config("pq_third".to_string(), config.clone()).unwrap();
+        let commit = peer_group.add_member(&third.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        pq_group.process_commit(&commit.commit()).unwrap();
+        assert_eq!(pq_group.get_current_epoch().unwrap(), peer_group.get_current_epoch().unwrap());
+        assert_eq!(pq_group.get_member_count(), 3);
+        
+        let reply = peer_group.encrypt_message(b"Received").unwrap();
+        let reply: opencall_mls::MLSCiphertext = serde_wasm_bindgen::from_value(reply).unwrap();
+        assert_eq!(pq_group.decrypt_message(&reply.data()).unwrap(), b"Received");
+        
+        // Ed25519 clients can host X-Wing groups next to classical ones
+        let client = MLSClient::new("test_user".to_string()).unwrap();
+        let group = client.create_group_with_config(vec![55, 56, 57, 58], config).unwrap();
+        assert_eq!(group.ciphersuite(), "MLS_256_XWING_CHACHA20POLY1305_SHA256_Ed25519");
+        
+        // A classical key package cannot join a hybrid group
+        let key_package = client.export_key_package().unwrap();
+        let error = group.add_member(&key_package).unwrap_err();
+        assert!(error.to_string().contains("Ciphersuite mismatch"));
+    }
+    
+    fn certificate_client(name: &str) -> MLSClient {
+        let (certificate, private_key): (&[u8], &[u8]) = match name {
+            "alice" => (include_bytes!("fixtures/x509/alice.der"), include_bytes!("fixtures/x509/alice.sk")),
+            "bob" => (include_bytes!("fixtures/x509/bob.der"), include_bytes!("fixtures/x509/bob.sk")),
+            _ => (include_bytes!("fixtures/x509/mallory.der"), include_bytes!("fixtures/x509/mallory.sk")),
+        };
+        let chain = js_sys::Array::of1(&js_sys::Uint8Array::from(certificate));
+        MLSClient::with_certificate(chain, private_key, JsValue::UNDEFINED).unwrap()
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_x509_credentials() {
+        let root = include_bytes!("fixtures/x509/root.der");
+        let alice = certificate_client("alice");
+        let bob = certificate_client("bob");
+        alice.add_trust_anchor(root).unwrap();
+        bob.add_trust_anchor(root).unwrap();
+        
+        // A chain issued by the trusted root is accepted on both ends
+        let group = alice.create_group(vec![59, 60, 61, 62]).unwrap();
+        let commit = group.add_member(&bob.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        assert!(bob.join_group(&commit.welcome()[0].to_vec()).is_ok());
+        
+        // Bob removed by the common name of his certificate
+        assert!(group.remove_member("bob").is_ok());
+        
+        // A certificate claiming to be bob from another root is not
+        let mallory = certificate_client("mallory");
+        let error = group.add_member(&mallory.export_key_package().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Invalid credential"));
+        
+        // Nor is a self-asserted basic credential
+        let basic = MLSClient::new("bob".to_string()).unwrap();
+        assert!(group.add_member(&basic.export_key_package().unwrap()).is_err());
+        assert_eq!(group.get_member_count(), 1);
+        
+        // The private key has to match the leaf certificate
+        let chain = js_sys::Array::of1(&js_sys::Uint8Array::from(&include_bytes!("fixtures/x509/alice.der")[..]));
+        let wrong_key = include_bytes!("fixtures/x509/bob.sk");
+        assert!(MLSClient::with_certificate(chain, wrong_key, JsValue::UNDEFINED).is_err());
+        
+        // EC keys are only accepted on P-256
+        let chain = js_sys::Array::of1(&js_sys::Uint8Array::from(&include_bytes!("fixtures/x509/carol-p384.der")[..]));
+        let config = js_sys::JSON::parse(r#"{ "ciphersuite": "MLS_128_DHKEMP256_AES128GCM_SHA256_P256" }"#).unwrap();
+        let error = MLSClient::with_certificate(chain, &[1u8; 48], config).err().unwrap();
+        assert!(error.to_string().contains("Unsupported certificate key curve"), "{}", error);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_js_credential_validator() {
+        let client1 = MLSClient::new("user1".to_string()).unwrap();
+        let client2 = MLSClient::new("user2".to_string()).unwrap();
+        let mallory = MLSClient::new("mallory".to_string()).unwrap();
+        
+        let validator = js_sys::Function::new_with_args(
+            "member",
+            "return member.userId === 'mallory' ? 'mallory is banned' : true;",
+        );
+        client1.set_js_credential_validator(Some(validator));
+        
+        let group = client1.create_group(vec![63, 64, 65, 66]).unwrap();
+        let error = group.add_member(&mallory.export_key_package().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Credential rejected: mallory is banned"));
+        assert_eq!(group.get_member_count(), 1);
+        
+        // A veto while joining leaves nothing behind
+        let commit = group.add_member(&client2.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        client2.set_js_credential_validator(Some(js_sys::Function::new_with_args(
+            "member",
+            "if (member.credentialType !== 'x509') throw new Error('certificates only');",
+        )));
+        let error = client2.join_group(&commit.welcome()[0].to_vec()).unwrap_err();
+        assert!(error.to_string().contains("certificates only"));
+        assert!(client2.load_group(vec![63, 64, 65, 66]).is_err());
+        
+        // Removing the validator accepts the same welcome
+        client2.set_js_credential_validator(None);
+        assert!(client2.join_group(&commit.welcome()[0].to_vec()).is_ok());
+    }
+    
+    struct DenyList(Vec<&'static str>);
+    
+    impl CredentialValidator for DenyList {
+        fn validate(&self, _group_id: &[u8], credential: &Credential, _signature_key: &[u8]) -> Result<(), String> {
+            match credential {
+                Credential::Basic(identity) => match Identity::parse(identity) {
+                    Ok(identity) if self.0.contains(&identity.user_id.as_str()) => Err("denied".to_string()),
+                    _ => Ok(()),
+                },
+                _ => Ok(()),
+            }
+        }
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_native_credential_validator() {
+        let client1 = MLSClient::new("user1".to_string()).unwrap();
+        let client2 = MLSClient::new("user2".to_string()).unwrap();
+        let client3 = MLSClient::new("user3".to_string()).unwrap();
+        
+        // user2 adds user3, which user1 refuses when staging the commit
+        let group1 = client1.create_group(vec![67, 68, 69, 70]).unwrap();
+        let commit = group1.add_member(&client2.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        let group2 = client2.join_group(&commit.welcome()[0].to_vec()).unwrap();
+        
+        client1.set_credential_validator(DenyList(vec!["user3"]));
+        let commit = group2.add_member(&client3.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        
+        let epoch = group1.get_current_epoch().unwrap();
+        let error = group1.process_commit(&commit.commit()).unwrap_err();
+        assert!(error.to_string().contains("Credential rejected"));
+        assert_eq!(group1.get_current_epoch().unwrap(), epoch);
+        assert_eq!(group1.get_member_count(), 2);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_attested_credentials() {
+        let issuer = CredentialIssuer::new(&[7; 32]).unwrap();
+        let expires_at = (js_sys::Date::now() / 1000.0) as u64 + 3600;
+        let attested = |name: &str, expires_at: u64| {
+            let mut client = MLSClient::new(name.to_string()).unwrap();
+            let attestation = issuer
+                .issue(name.to_string(), &client.signature_public_key(), expires_at, "srp-session".to_string())
+                .unwrap();
+            client.set_attestation(&attestation).unwrap();
+            client.set_attestation_key(Some(issuer.public_key()));
+            client
+        };
+        
+        let client1 = attested("user1", expires_at);
+        let client2 = attested("user2", expires_at);
+        let group = client1.create_group(vec![71, 72, 73, 74]).unwrap();
+        let commit = group.add_member(&client2.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        assert!(client2.join_group(&commit.welcome()[0].to_vec()).is_ok());
+        
+        // Without an attestation key, attested and basic members alike are
+        // accepted unverified
+        let host = MLSClient::new("host".to_string()).unwrap();
+        let open_group = host.create_group(vec![195, 196, 197, 198]).unwrap();
+        for member in [attested("user5", expires_at), MLSClient::new("user6".to_string()).unwrap()] {
+            let key_package = member.export_key_package().unwrap();
+            let info: KeyPackageInfo =
+                serde_wasm_bindgen::from_value(host.inspect_key_package(&key_package).unwrap()).unwrap();
+            assert!(info.trust_error.is_none() && !info.verified);
+            open_group.add_member(&key_package).unwrap();
+        }
+        assert_eq!(open_group.get_member_count(), 3);
+        
+        // Unattested and expired members are refused
+        assert_eq!(group.get_member_count(), 2);
+        let basic = MLSClient::new("user3".to_string()).unwrap();
+        let error = group.add_member(&basic.export_key_package().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Basic credentials are not accepted"), "{}", error);
+        let expired = attested("user4", 1);
+        let error = group.add_member(&expired.export_key_package().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Attestation has expired"), "{}", error);
+        
+        // So are attestations from another server
+        let rogue = CredentialIssuer::new(&[8; 32]).unwrap();
+        let mut impostor = MLSClient::new("user1".to_string()).unwrap();
+        let forged = rogue
+            .issue("user1".to_string(), &impostor.signature_public_key(), expires_at, "forged".to_string())
+            .unwrap();
+        impostor.set_attestation(&forged).unwrap();
+        let error = group.add_member(&impostor.export_key_package().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Bad attestation signature"), "{}", error);
+        assert_eq!(group.get_member_count(), 2);
+        
+        // An attestation for someone else's key cannot be adopted
+        let stolen = issuer
+            .issue("user1".to_string(), &client1.signature_public_key(), expires_at, "srp-session".to_string())
+            .unwrap();
+        assert!(impostor.set_attestation(&stolen).is_err());
+        
+        // The normalized identity is attested, whatever its spelling
+        let mut spelled = MLSClient::new("user7".to_string()).unwrap();
+        let attestation = issuer
+            .issue("USER7".to_string(), &spelled.signature_public_key(), expires_at, "srp-session".to_string())
+            .unwrap();
+        spelled.set_attestation(&attestation).unwrap();
+        group.add_member(&spelled.export_key_package().unwrap()).unwrap();
+        assert_eq!(group.get_member_count(), 3);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_rotate_signature_key() {
+        let mut client1 = MLSClient::new("user1".to_string()).unwrap();
+        let client2 = MLSClient::new("user2".to_string()).unwrap();
+        let group1 = client1.create_group(vec![75, 76, 77, 78]).unwrap();
+        client1.create_group(vec![79, 80, 81, 82]).unwrap();
+        let commit = group1.add_member(&client2.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        let group2 = client2.join_group(&commit.welcome()[0].to_vec()).unwrap();
+        
+        let old_key = client1.signature_public_key();
+        let epoch = group1.get_current_epoch().unwrap();
+        let commits = client1.rotate_signature_key().unwrap();
+        let commits: Vec<GroupCommit> = serde_wasm_bindgen::from_value(commits).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_ne!(client1.signature_public_key(), old_key);
+        
+        // The handle taken before the rotation follows the new epoch
+        assert_eq!(group1.get_current_epoch().unwrap(), epoch + 1);
+        
+        // The other member moves to the new key with the update commit
+        let update = commits.iter().find(|c| c.group_id() == vec![75, 76, 77, 78]).unwrap();
+        group2.process_commit(&update.commit()).unwrap();
+        let message = group1.encrypt_message(b"signed with the new key").unwrap();
+        let message: opencall_mls::MLSCiphertext = serde_wasm_bindgen::from_value(message).unwrap();
+        assert_eq!(group2.decrypt_message(&message.data()).unwrap(), b"signed with the new key");
+        
+        // The exported identity carries the new key
+        let restored = MLSClient::restore("user1".to_string(), &client1.export_state().unwrap()).unwrap();
+        assert_eq!(restored.signature_public_key(), client1.signature_public_key());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_rotate_skips_groups_left() {
+        let client1 = MLSClient::new("user1".to_string()).unwrap();
+        let mut client2 = MLSClient::new("user2".to_string()).unwrap();
+        let join = |group: &opencall_mls::MLSGroup| {
+            let commit = group.add_member(&client2.export_key_package().unwrap()).unwrap();
+            let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+            client2.join_group(&commit.welcome()[0].to_vec()).unwrap()
+        };
+        let kept = client1.create_group(vec![167, 168, 169, 170]).unwrap();
+        let left = client1.create_group(vec![171, 172, 173, 174]).unwrap();
+        join(&kept);
+        let removed = join(&left);
+        
+        // user2 learns it was removed, which leaves its copy of the group inactive
+        let commit = left.remove_member("user2").unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        removed.process_commit(&commit.commit()).unwrap();
+        
+        let commits = client2.rotate_signature_key().unwrap();
+        let commits: Vec<GroupCommit> = serde_wasm_bindgen::from_value(commits).unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].group_id(), vec![167, 168, 169, 170]);
+        kept.process_commit(&commits[0].commit()).unwrap();
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_identity_backup() {
+        let client = MLSClient::new("user1".to_string()).unwrap();
+        let key_package = client.export_key_package().unwrap();
+        let recovery_key: JsValue = js_sys::Uint8Array::from(&[9u8; 32][..]).into();
+        let bundle = client.export_backup(recovery_key.clone(), true).unwrap();
+        
+        // A Welcome sent to a key package from before the loss still works
+        let inviter = MLSClient::new("user2".to_string()).unwrap();
+        let group = inviter.create_group(vec![83, 84, 85, 86]).unwrap();
+        let commit = group.add_member(&key_package).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        
+        let recovered = MLSClient::import_backup("user1".to_string(), &bundle, recovery_key.clone()).unwrap();
+        assert_eq!(recovered.signature_public_key(), client.signature_public_key());
+        assert!(recovered.join_group(&commit.welcome()[0].to_vec()).is_ok());
+        
+        // Any altered byte of the sealed payload is detected
+        let mut tampered = bundle.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let error = MLSClient::import_backup("user1".to_string(), &tampered, recovery_key.clone()).unwrap_err();
+        assert!(error.to_string().contains("tampered"));
+        assert!(MLSClient::import_backup("user1".to_string(), &bundle[..20], recovery_key).is_err());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_identity_backup_wrong_secret() {
+        let client = MLSClient::new("user1".to_string()).unwrap();
+        let bundle = client.export_backup(JsValue::from_str("correct horse"), false).unwrap();
+        
+        let error = MLSClient::import_backup("user1".to_string(), &bundle, JsValue::from_str("battery staple"))
+            .unwrap_err();
+        assert!(error.to_string().contains("Wrong passphrase"));
+        
+        // A raw key cannot open a passphrase bundle either
+        let key: JsValue = js_sys::Uint8Array::from(&[9u8; 32][..]).into();
+        assert!(MLSClient::import_backup("user1".to_string(), &bundle, key).is_err());
+        
+        let recovered =
+            MLSClient::import_backup("user1".to_string(), &bundle, JsValue::from_str("correct horse")).unwrap();
+        assert_eq!(recovered.signature_public_key(), client.signature_public_key());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_identity_backup_header_tampering() {
+        let client = MLSClient::new("user1".to_string()).unwrap();
+        let secret = JsValue::from_str("correct horse");
+        let bundle = client.export_backup(secret.clone(), false).unwrap();
+        
+        let header_len = u32::from_be_bytes(bundle[6..10].try_into().unwrap()) as usize;
+        let with_header = |header: &[u8]| {
+            let mut tampered = bundle[..6].to_vec();
+            tampered.extend_from_slice(&(header.len() as u32).to_be_bytes());
+            tampered.extend_from_slice(header);
+            tampered.extend_from_slice(&bundle[10 + header_len..]);
+            tampered
+        };
+        let header = &bundle[10..10 + header_len];
+        
+        // A header that still derives the same key is bound to the payload
+        let padded = [header, b" "].concat();
+        let error = MLSClient::import_backup("user1".to_string(), &with_header(&padded), secret.clone()).unwrap_err();
+        assert!(error.to_string().contains("tampered"), "{}", error);
+        
+        // Costs beyond the bounds are refused before deriving anything
+        let mut costly: serde_json::Value = serde_json::from_slice(header).unwrap();
+        costly["kdf"]["Argon2id"]["m_cost"] = serde_json::json!(u32::MAX);
+        let costly = serde_json::to_vec(&costly).unwrap();
+        let error = MLSClient::import_backup("user1".to_string(), &with_header(&costly), secret.clone()).unwrap_err();
+        assert!(error.to_string().contains("tampered"), "{}", error);
+        
+        // The version 1 layout left the header unbound and is refused
+        let mut downgraded = bundle.clone();
+        downgraded[4..6].copy_from_slice(&1u16.to_be_bytes());
+        let error = MLSClient::import_backup("user1".to_string(), &downgraded, secret.clone()).unwrap_err();
+        assert!(error.to_string().contains("Unsupported storage version 1"), "{}", error);
+        
+        assert!(MLSClient::import_backup("user1".to_string(), &with_header(header), secret).is_ok());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_multi_device_users() {
+        let host = MLSClient::for_device("host", "desktop", JsValue::UNDEFINED).unwrap();
+        let laptop = MLSClient::for_device("alice", "laptop", JsValue::UNDEFINED).unwrap();
+        let phone = MLSClient::for_device("alice", "phone", JsValue::UNDEFINED).unwrap();
+        let bob = MLSClient::for_device("bob", "phone", JsValue::UNDEFINED).unwrap();
+        let group = host.create_group(vec![87, 88, 89, 90]).unwrap();
+        
+        // Both of alice's devices arrive in one commit
+        let epoch = group.get_current_epoch().unwrap();
+        let devices = js_sys::Array::of2(
+            &js_sys::Uint8Array::from(&laptop.export_key_package().unwrap()[..]),
+            &js_sys::Uint8Array::from(&phone.export_key_package().unwrap()[..]),
+        );
+        group.add_user_devices(devices).unwrap();
+        assert_eq!(group.get_current_epoch().unwrap(), epoch + 1);
+        group.add_member(&bob.export_key_package().unwrap()).unwrap();
+        
+        let users: Vec<UserDevices> = serde_wasm_bindgen::from_value(group.list_devices().unwrap()).unwrap();
+        let alice = users.iter().find(|user| user.user_id == "alice").unwrap();
+        assert_eq!(alice.devices, vec![Some("laptop".to_string()), Some("phone".to_string())]);
+        
+        // Devices of different users cannot be mixed
+        let mixed = js_sys::Array::of2(
+            &js_sys::Uint8Array::from(&MLSClient::for_device("carol", "a", JsValue::UNDEFINED).unwrap().export_key_package().unwrap()[..]),
+            &js_sys::Uint8Array::from(&MLSClient::for_device("dave", "a", JsValue::UNDEFINED).unwrap().export_key_package().unwrap()[..]),
+        );
+        assert!(group.add_user_devices(mixed).is_err());
+        
+        // One device, then the whole user
+        group.remove_member("bob#phone").unwrap();
+        assert_eq!(group.get_member_count(), 3);
+        group.remove_user("alice").unwrap();
+        assert_eq!(group.get_member_count(), 1);
+        assert!(group.remove_user("alice").is_err());
+        
+        assert!(MLSClient::for_device("eve#x", "phone", JsValue::UNDEFINED).is_err());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_member_fingerprints() {
+        let mut client1 = MLSClient::new("user1".to_string()).unwrap();
+        let client2 = MLSClient::new("user2".to_string()).unwrap();
+        let group1 = client1.create_group(vec![91, 92, 93, 94]).unwrap();
+        let commit = group1.add_member(&client2.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        let group2 = client2.join_group(&commit.welcome()[0].to_vec()).unwrap();
+        
+        // Both sides compute the same numbers
+        let fingerprints = |group: &opencall_mls::MLSGroup| -> Vec<MemberFingerprint> {
+            serde_wasm_bindgen::from_value(group.member_fingerprints().unwrap()).unwrap()
+        };
+        let numbers = |group: &opencall_mls::MLSGroup| {
+            let mut numbers: Vec<(String, String)> =
+                fingerprints(group).into_iter().map(|f| (f.identity, f.numeric)).collect();
+            numbers.sort();
+            numbers
+        };
+        assert_eq!(numbers(&group1), numbers(&group2));
+        assert_eq!(numbers(&group1)[0].1.len(), 35);
+        assert_eq!(group1.verification_code().unwrap(), group2.verification_code().unwrap());
+        
+        // Verification survives reloading the group
+        group2.mark_verified("user1").unwrap();
+        assert!(group2.mark_verified("nobody").is_err());
+        let reloaded = client2.load_group(vec![91, 92, 93, 94]).unwrap();
+        let user1 = fingerprints(&reloaded).into_iter().find(|f| f.identity == "user1").unwrap();
+        assert!(user1.verified && !user1.key_changed);
+        
+        // A new key for a verified identity is flagged
+        let code = group2.verification_code().unwrap();
+        let commits: Vec<GroupCommit> =
+            serde_wasm_bindgen::from_value(client1.rotate_signature_key().unwrap()).unwrap();
+        group2.process_commit(&commits[0].commit()).unwrap();
+        let user1 = fingerprints(&group2).into_iter().find(|f| f.identity == "user1").unwrap();
+        assert!(!user1.verified && user1.key_changed);
+        assert_ne!(group2.verification_code().unwrap(), code);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_structured_identity() {
+        // IDs are normalized; display names are kept but not matched on
+        let identity = Identity::new("ＡＬＩＣＥ", Some("Laptop"), Some("  Alice Liddell ")).unwrap();
+        assert_eq!(identity.to_string(), "alice#laptop");
+        assert_eq!(identity.display_name.as_deref(), Some("Alice Liddell"));
+        assert_eq!(Identity::parse(&identity.encode()).unwrap(), identity);
+        assert!(identity.same_member(&"Alice#LAPTOP".parse().unwrap()));
+        
+        for invalid in ["", "al ice", "al\u{200B}ice", "al#ice"] {
+            assert!(Identity::new(invalid, None, None).is_err(), "{:?}", invalid);
+        }
+        assert!(Identity::new("alice", None, Some(&"x".repeat(65))).is_err());
+        // Legacy text identities still parse
+        assert_eq!(Identity::parse(b"bob#phone").unwrap().device_id.as_deref(), Some("phone"));
+        
+        let host = MLSClient::new("Host".to_string()).unwrap();
+        let laptop = MLSClient::for_device("alice", "laptop", JsValue::UNDEFINED).unwrap();
+        let phone = MLSClient::for_device("ALICE", "phone", JsValue::UNDEFINED).unwrap();
+        let bob = MLSClient::with_identity(
+            serde_wasm_bindgen::to_value(&Identity::new("bob", None, Some("Bob")).unwrap()).unwrap(),
+            JsValue::UNDEFINED,
+        )
+        .unwrap();
+        assert!(MLSClient::restore("HOST".to_string(), &host.export_state().unwrap()).is_ok());
+        
+        let group = host.create_group(vec![95, 96, 97, 98]).unwrap();
+        for client in [&laptop, &phone, &bob] {
+            group.add_member(&client.export_key_package().unwrap()).unwrap();
+        }
+        
+        let users: Vec<UserDevices> = serde_wasm_bindgen::from_value(group.list_devices().unwrap()).unwrap();
+        let bob_devices = users.iter().find(|user| user.user_id == "bob").unwrap();
+        assert_eq!(bob_devices.display_name.as_deref(), Some("Bob"));
+        
+        // A bare user ID is ambiguous once the user has several devices
+        let error = group.remove_member("alice").unwrap_err().to_string();
+        assert!(error.contains("alice#laptop") && error.contains("alice#phone"), "{}", error);
+        assert_eq!(group.get_member_count(), 4);
+        
+        group.remove_member("Alice#Phone").unwrap();
+        group.remove_member("alice").unwrap();
+        group.remove_member("BOB").unwrap();
+        assert_eq!(group.get_member_count(), 1);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_tenant_follows_normalized_identity() {
+        let store = memory_store();
+        let client =
+            MLSClient::with_storage("Alice#Phone".to_string(), store.clone(), None, JsValue::UNDEFINED).unwrap();
+        client.create_group(vec![143, 144, 145, 146]).unwrap();
+        assert_eq!(client.list_namespaces().unwrap(), vec!["alice#phone".to_string()]);
+        
+        // Another spelling of the same identity opens the same records
+        let restored = MLSClient::restore_with_storage(
+            "ALICE#phone".to_string(),
+            &client.export_state().unwrap(),
+            store,
+            None,
+        )
+        .unwrap();
+        assert!(restored.load_group(vec![143, 144, 145, 146]).is_ok());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_key_package_pool() {
+        let client1 = MLSClient::new("user1".to_string()).unwrap();
+        let client2 = MLSClient::new("user2".to_string()).unwrap();
+        assert!(client2.generate_key_packages(0, false).is_err());
+        
+        let key_packages = client2.generate_key_packages(3, true).unwrap();
+        assert_eq!(key_packages.len(), 3);
+        // The last-resort package is not counted
+        assert_eq!(client2.unused_key_package_count().unwrap(), 2);
+        
+        let join = |group_id: Vec<u8>, key_package: &js_sys::Uint8Array| {
+            let group = client1.create_group(group_id).unwrap();
+            let commit = group.add_member(&key_package.to_vec()).unwrap();
+            let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+            client2.join_group(&commit.welcome()[0].to_vec()).unwrap();
+        };
+        
+        // The used package is recorded as consumed until compaction
+        let consumed = || {
+            let snapshot = client2.export_storage().unwrap();
+            snapshot.windows(b"consumed_key_package".len()).filter(|w| w == b"consumed_key_package").count()
+        };
+        join(vec![99, 100, 101, 102], &key_packages[0]);
+        assert_eq!(client2.unused_key_package_count().unwrap(), 1);
+        assert_eq!(consumed(), 1);
+        client2.compact(0).unwrap();
+        assert_eq!(client2.unused_key_package_count().unwrap(), 1);
+        assert_eq!(consumed(), 0);
+        
+        // The last-resort package survives being used more than once
+        join(vec![103, 104, 105, 106], &key_packages[2]);
+        join(vec![107, 108, 109, 110], &key_packages[2]);
+        assert_eq!(consumed(), 0);
+        client2.compact(0).unwrap();
+        assert_eq!(client2.unused_key_package_count().unwrap(), 1);
+        
+        client2.export_key_package().unwrap();
+        assert_eq!(client2.unused_key_package_count().unwrap(), 2);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_compact_epoch_keys() {
+        let client = MLSClient::new("user1".to_string()).unwrap();
+        let group = client.create_group(vec![159, 160, 161, 162]).unwrap();
+        for user in ["user2", "user3", "user4", "user5"] {
+            let key_package = MLSClient::new(user.to_string()).unwrap().export_key_package().unwrap();
+            group.add_member(&key_package).unwrap();
+        }
+        let current = group.get_current_epoch().unwrap();
+        
+        let epochs = || {
+            let mut epochs: Vec<u64> = snapshot_records(&client.export_storage().unwrap())
+                .into_iter()
+                .filter(|(namespace, _, _)| namespace == "epoch_key_pairs")
+                .map(|(_, key, _)| serde_json::from_slice::<(serde_json::Value, u64, u32)>(&key).unwrap().1)
+                .collect();
+            epochs.sort();
+            epochs.dedup();
+            epochs
+        };
+        let before = epochs();
+        assert_eq!(before.last(), Some(&current));
+        
+        // Keys of the current epoch and the one before it survive
+        client.compact(1).unwrap();
+        let after = epochs();
+        assert_eq!(after, before.into_iter().filter(|epoch| epoch + 1 >= current).collect::<Vec<_>>());
+        assert!(after.contains(&current));
+        assert_eq!(client.compact(1).unwrap().records_removed(), 0);
+        
+        // The group still works on the retained keys
+        assert!(group.encrypt_message(b"after compaction").is_ok());
+    }
+    
+    struct FixedClock(u64);
+    
+    impl Clock for FixedClock {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_key_package_lifetime() {
+        // openmls also checks lifetimes against the system clock, so the key
+        // package must be valid in real time too
+        let issued = (js_sys::Date::now() / 1000.0) as u64 - 60;
+        const LIFETIME: u64 = 3600;
+        
+        let mut client2 = MLSClient::new("user2".to_string()).unwrap();
+        client2.set_clock(FixedClock(issued));
+        assert!(client2.set_key_package_lifetime(0).is_err());
+        client2.set_key_package_lifetime(LIFETIME as u32).unwrap();
+        let key_package = client2.export_key_package().unwrap();
+        
+        let add_at = |now: u64| {
+            let client1 = MLSClient::new("user1".to_string()).unwrap();
+            client1.set_clock(FixedClock(now));
+            let group = client1.create_group(vec![111, 112, 113, 114]).unwrap();
+            group.add_member(&key_package).map(|_| group.get_member_count())
+        };
+        
+        // Clocks a few minutes apart still agree
+        assert_eq!(add_at(issued - 60).unwrap(), 2);
+        assert_eq!(add_at(issued + LIFETIME + 60).unwrap(), 2);
+        
+        let error = add_at(issued - 3600).unwrap_err().to_string();
+        assert!(error.contains("not valid before"), "{}", error);
+        let error = add_at(issued + LIFETIME + 3600).unwrap_err().to_string();
+        assert!(error.contains("expired"), "{}", error);
+        
+        // A JS clock works the same way
+        let client1 = MLSClient::new("user1".to_string()).unwrap();
+        let late = (issued + 2 * LIFETIME) * 1000;
+        client1.set_js_clock(Some(js_sys::Function::new_no_args(&format!("return {};", late))));
+        let group = client1.create_group(vec![115, 116, 117, 118]).unwrap();
+        assert!(group.add_member(&key_package).is_err());
+        assert_eq!(group.get_member_count(), 1);
+        
+        // The configured lifetime survives exporting and restoring the client
+        let restored = MLSClient::restore("user2".to_string(), &client2.export_state().unwrap()).unwrap();
+        let info: KeyPackageInfo =
+            serde_wasm_bindgen::from_value(restored.inspect_key_package(&restored.export_key_package().unwrap()).unwrap())
+                .unwrap();
+        assert_eq!(info.not_after - info.not_before, LIFETIME);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_inspect_key_package() {
+        let host = MLSClient::new("host".to_string()).unwrap();
+        let guest = MLSClient::with_identity(
+            serde_wasm_bindgen::to_value(&Identity::new("alice", Some("phone"), Some("Alice")).unwrap()).unwrap(),
+            JsValue::UNDEFINED,
+        )
+        .unwrap();
+        let key_package = guest.generate_key_packages(1, true).unwrap()[0].to_vec();
+        
+        let info: KeyPackageInfo =
+            serde_wasm_bindgen::from_value(host.inspect_key_package(&key_package).unwrap()).unwrap();
+        assert_eq!(info.identity, "alice#phone");
+        assert_eq!(info.display_name.as_deref(), Some("Alice"));
+        assert_eq!(info.credential_type, "basic");
+        assert_eq!(info.signature_key, guest.signature_public_key());
+        assert_eq!(info.ciphersuite, host.ciphersuite());
+        assert!(info.last_resort && info.lifetime_valid && info.trust_error.is_none());
+        assert!(!info.capabilities.ciphersuites.is_empty());
+        
+        // Inspection leaves groups alone, and the fingerprint matches the one
+        // shown after admission
+        let group = host.create_group(vec![119, 120, 121, 122]).unwrap();
+        let epoch = group.get_current_epoch().unwrap();
+        host.inspect_key_package(&key_package).unwrap();
+        assert_eq!(group.get_current_epoch().unwrap(), epoch);
+        group.add_member(&key_package).unwrap();
+        let fingerprints: Vec<MemberFingerprint> =
+            serde_wasm_bindgen::from_value(group.member_fingerprints().unwrap()).unwrap();
+        assert!(fingerprints.iter().any(|f| f.identity == "alice#phone" && f.numeric == info.fingerprint));
+        
+        // A tampered signature does not verify
+        let mut tampered = key_package.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(host.inspect_key_package(&tampered).is_err());
+        
+        // Policy problems are reported rather than failing the inspection
+        host.set_attestation_key(Some(CredentialIssuer::new(&[7; 32]).unwrap().public_key()));
+        let info: KeyPackageInfo =
+            serde_wasm_bindgen::from_value(host.inspect_key_package(&key_package).unwrap()).unwrap();
+        assert!(info.trust_error.is_some());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_capabilities() {
+        let mut host = MLSClient::new("host".to_string()).unwrap();
+        host.set_capabilities(vec!["SFrame".to_string(), "screen-share".to_string(), "files".to_string()]).unwrap();
+        let mut modern = MLSClient::new("modern".to_string()).unwrap();
+        modern.set_capabilities(vec!["sframe".to_string(), "screen-share".to_string()]).unwrap();
+        modern.set_leaf_extension(0xF100, Some(b"v2".to_vec())).unwrap();
+        let legacy = MLSClient::new("legacy".to_string()).unwrap();
+        
+        // Reserved and non-private extension types are refused
+        assert!(modern.set_leaf_extension(0x0002, Some(vec![1])).is_err());
+        assert!(modern.set_leaf_extension(0xF0C2, Some(vec![1])).is_err());
+        
+        let config = serde_wasm_bindgen::to_value(&serde_json::json!({ "requiredCapabilities": ["sframe"] })).unwrap();
+        let group = host.create_group_with_config(vec![123, 124, 125, 126], config).unwrap();
+        
+        let error = group.add_member(&legacy.export_key_package().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Missing required capabilities: sframe"), "{}", error);
+        assert_eq!(group.get_member_count(), 1);
+        
+        let commit = group.add_member(&modern.export_key_package().unwrap()).unwrap();
+        let commit: opencall_mls::MLSCommit = serde_wasm_bindgen::from_value(commit).unwrap();
+        let joined = modern.join_group(&commit.welcome()[0].to_vec()).unwrap();
+        
+        let common = |group: &opencall_mls::MLSGroup| -> GroupCapabilities {
+            serde_wasm_bindgen::from_value(group.common_capabilities().unwrap()).unwrap()
+        };
+        for view in [common(&group), common(&joined)] {
+            assert_eq!(view.features, vec!["screen-share".to_string(), "sframe".to_string()]);
+            assert_eq!(view.required, vec!["sframe".to_string()]);
+        }
+        assert_eq!(group.member_extension("modern", 0xF100).unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(group.member_extension("host", 0xF100).unwrap(), None);
+        
+        // Groups without requirements admit anyone
+        let open = host.create_group(vec![127, 128, 129, 130]).unwrap();
+        open.add_member(&legacy.export_key_package().unwrap()).unwrap();
+        assert!(common(&open).features.is_empty());
+        
+        // Capabilities survive exporting and restoring the client
+        let restored = MLSClient::restore("modern".to_string(), &modern.export_state().unwrap()).unwrap();
+        let key_package = restored.export_key_package().unwrap();
+        let info: KeyPackageInfo = serde_wasm_bindgen::from_value(host.inspect_key_package(&key_package).unwrap()).unwrap();
+        assert!(info.leaf_extensions.contains(&0xF100));
+        let config = serde_wasm_bindgen::to_value(&serde_json::json!({ "requiredCapabilities": ["sframe"] })).unwrap();
+        let required = host.create_group_with_config(vec![191, 192, 193, 194], config).unwrap();
+        required.add_member(&key_package).unwrap();
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_batch_add_and_remove() {
+        let host = MLSClient::new("host".to_string()).unwrap();
+        let guests: Vec<MLSClient> = (0..5).map(|i| MLSClient::new(format!("guest{}", i)).unwrap()).collect();
+        let group = host.create_group(vec![131, 132, 133, 134]).unwrap();
+        group.add_member(&guests[0].export_key_package().unwrap()).unwrap();
+        let epoch = group.get_current_epoch().unwrap();
+        
+        let key_packages = js_sys::Array::new();
+        for bytes in [
+            guests[1].export_key_package().unwrap(),
+            guests[2].export_key_package().unwrap(),
+            guests[0].export_key_package().unwrap(),
+            guests[1].export_key_package().unwrap(),
+            vec![1, 2, 3],
+            guests[3].export_key_package().unwrap(),
+        ] {
+            key_packages.push(&js_sys::Uint8Array::from(&bytes[..]));
+        }
+        
+        let batch: BatchCommit = serde_wasm_bindgen::from_value(group.add_members(key_packages).unwrap()).unwrap();
+        let statuses: Vec<BatchStatus> = batch.items.iter().map(|item| item.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                BatchStatus::Added,
+                BatchStatus::Added,
+                BatchStatus::Duplicate,
+                BatchStatus::Duplicate,
+                BatchStatus::Rejected,
+                BatchStatus::Added,
+            ]
+        );
+        assert!(batch.items[4].reason.is_some());
+        
+        // One epoch and one Welcome for all newcomers
+        assert_eq!(group.get_current_epoch().unwrap(), epoch + 1);
+        assert_eq!(group.get_member_count(), 5);
+        let commit = batch.commit.unwrap();
+        assert_eq!(commit.welcome().len(), 1);
+        for guest in &guests[1..4] {
+            assert!(guest.join_group(&commit.welcome()[0].to_vec()).is_ok());
+        }
+        
+        let removal = vec!["guest1", "GUEST2", "guest1", "guest4", "guest3"].into_iter().map(String::from).collect();
+        let batch: BatchCommit = serde_wasm_bindgen::from_value(group.remove_members(removal).unwrap()).unwrap();
+        let statuses: Vec<BatchStatus> = batch.items.iter().map(|item| item.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                BatchStatus::Removed,
+                BatchStatus::Removed,
+                BatchStatus::Duplicate,
+                BatchStatus::Unknown,
+                BatchStatus::Removed,
+            ]
+        );
+        assert_eq!(batch.items[1].member.as_deref(), Some("guest2"));
+        assert_eq!(group.get_current_epoch().unwrap(), epoch + 2);
+        assert_eq!(group.get_member_count(), 2);
+        
+        // Nothing to do means no commit and no new epoch
+        let batch: BatchCommit =
+            serde_wasm_bindgen::from_value(group.remove_members(vec!["nobody".to_string()]).unwrap()).unwrap();
+        assert!(batch.commit.is_none());
+        assert_eq!(group.get_current_epoch().unwrap(), epoch + 2);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_commits_advance_epoch() {
+        let host = MLSClient::new("host".to_string()).unwrap();
+        let group = host.create_group(vec![135, 136, 137, 138]).unwrap();
+        let key_package = |user: &str, device: &str| {
+            MLSClient::for_device(user, device, JsValue::UNDEFINED).unwrap().export_key_package().unwrap()
+        };
+        let array = |key_packages: Vec<Vec<u8>>| {
+            let array = js_sys::Array::new();
+            for bytes in key_packages {
+                array.push(&js_sys::Uint8Array::from(&bytes[..]));
+            }
+            array
+        };
+        
+        // Every committing operation merges its own commit right away
+        let mut expected = group.get_current_epoch().unwrap();
+        let mut step = |group: &opencall_mls::MLSGroup, members: u32| {
+            expected += 1;
+            assert_eq!(group.get_current_epoch().unwrap(), expected);
+            assert_eq!(group.get_member_count(), members);
+        };
+        
+        group.add_member(&key_package("alice", "laptop")).unwrap();
+        step(&group, 2);
+        group.add_user_devices(array(vec![key_package("bob", "laptop"), key_package("bob", "phone")])).unwrap();
+        step(&group, 4);
+        group.add_members(array(vec![key_package("carol", "phone"), key_package("dave", "phone")])).unwrap();
+        step(&group, 6);
+        group.remove_member("alice#laptop").unwrap();
+        step(&group, 5);
+        group.remove_user("bob").unwrap();
+        step(&group, 3);
+        group.remove_members(vec!["carol".to_string(), "dave".to_string()]).unwrap();
+        step(&group, 1);
+    }
+    
+    /// An in-memory JS store. Setting `failAfter` to `n` lets the next `n`
+    /// puts succeed and makes every later one throw.
+    fn memory_store() -> JsValue {
+        js_sys::Function::new_no_args(
+            r#"
+            const records = new Map();
+            const id = (namespace, key) => namespace + "/" + Array.from(key).join(",");
+            return {
+                records,
+                failAfter: null,
+                get(namespace, key) {
+                    const record = records.get(id(namespace, key));
+                    return record ? record[2] : null;
+                },
+                put(namespace, key, value) {
+                    if (this.failAfter !== null && this.failAfter-- <= 0) {
+                        throw new Error("Storage is full");
+                    }
+                    records.set(id(namespace, key), [namespace, key.slice(), value.slice()]);
+                },
+                delete(namespace, key) {
+                    records.delete(id(namespace, key));
+                },
+                entries() {
+                    return Array.from(records.values());
+                },
+                clear() {
+                    records.clear();
+                },
+            };
+            "#,
+        )
+        .call0(&JsValue::NULL)
+        .unwrap()
+    }
+    
+    /// The raw `[namespace, key, value]` records held by a `memory_store`
+    fn stored_records(store: &JsValue) -> Vec<js_sys::Array> {
+        let records: js_sys::Map = js_sys::Reflect::get(store, &"records".into()).unwrap().into();
+        records.values().into_iter().map(|record| record.unwrap().into()).collect()
+    }
+    
+    fn fail_puts_after(store: &JsValue, puts: usize) {
+        js_sys::Reflect::set(store, &"failAfter".into(), &JsValue::from(puts as u32)).unwrap();
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_encrypted_storage() {
+        let store = memory_store();
+        let client = MLSClient::with_encrypted_storage(
+            "user1".to_string(),
+            store.clone(),
+            JsValue::from_str("correct horse"),
+            None,
+            JsValue::UNDEFINED,
+        )
+        .unwrap();
+        client.create_group(vec![151, 152, 153, 154]).unwrap();
+        let state = client.export_state().unwrap();
+        
+        // Groups survive a restart under the same passphrase
+        let restore = |secret: &str| {
+            MLSClient::restore_with_encrypted_storage(
+                "user1".to_string(),
+                &state,
+                store.clone(),
+                JsValue::from_str(secret),
+                None,
+            )
+        };
+        let restored = restore("correct horse").unwrap();
+        assert_eq!(restored.load_group(vec![151, 152, 153, 154]).unwrap().get_current_epoch().unwrap(), 0);
+        
+        let error = restore("battery staple").err().unwrap();
+        assert!(error.to_string().contains("Wrong passphrase"), "{}", error);
+        
+        // Any altered byte of a sealed record is detected
+        for record in stored_records(&store) {
+            if record.get(0).as_string().unwrap() != "encryption" {
+                let value = js_sys::Uint8Array::new(&record.get(2));
+                let last = value.length() - 1;
+                value.set_index(last, value.get_index(last) ^ 1);
+            }
+        }
+        let error = restore("correct horse").unwrap().load_group(vec![151, 152, 153, 154]).err().unwrap();
+        assert!(error.to_string().contains("Failed to open record"), "{}", error);
+        
+        // A header demanding excessive key derivation costs is refused
+        for record in stored_records(&store) {
+            if record.get(0).as_string().unwrap() == "encryption" {
+                let value = js_sys::Uint8Array::new(&record.get(2)).to_vec();
+                let mut header: serde_json::Value = serde_json::from_slice(&value).unwrap();
+                header["kdf"]["Argon2id"]["m_cost"] = serde_json::json!(u32::MAX);
+                record.set(2, js_sys::Uint8Array::from(&serde_json::to_vec(&header).unwrap()[..]).into());
+            }
+        }
+        let error = restore("correct horse").err().unwrap();
+        assert!(error.to_string().contains("too high"), "{}", error);
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_rekey_storage() {
+        let store = memory_store();
+        let client = MLSClient::with_encrypted_storage(
+            "user1".to_string(),
+            store.clone(),
+            JsValue::from_str("correct horse"),
+            None,
+            JsValue::UNDEFINED,
+        )
+        .unwrap();
+        client.create_group(vec![155, 156, 157, 158]).unwrap();
+        let state = client.export_state().unwrap();
+        let restore = |secret: &str| {
+            MLSClient::restore_with_encrypted_storage(
+                "user1".to_string(),
+                &state,
+                store.clone(),
+                JsValue::from_str(secret),
+                None,
+            )
+            .and_then(|client| client.load_group(vec![155, 156, 157, 158]))
+        };
+        let records = stored_records(&store).len();
+        
+        // Failing while records are staged leaves the old passphrase in place
+        fail_puts_after(&store, 1);
+        assert!(client.rekey_storage(JsValue::from_str("battery staple")).is_err());
+        js_sys::Reflect::set(&store, &"failAfter".into(), &JsValue::NULL).unwrap();
+        assert!(restore("correct horse").is_ok());
+        assert!(restore("battery staple").is_err());
+        assert_eq!(stored_records(&store).len(), records);
+        
+        // Failing after the header is swapped is completed by the next open
+        fail_puts_after(&store, records);
+        assert!(client.rekey_storage(JsValue::from_str("battery staple")).is_err());
+        js_sys::Reflect::set(&store, &"failAfter".into(), &JsValue::NULL).unwrap();
+        assert!(restore("battery staple").is_ok());
+        assert_eq!(stored_records(&store).len(), records);
+        
+        let error = restore("correct horse").err().unwrap();
+        assert!(error.to_string().contains("Wrong passphrase"), "{}", error);
+        
+        // A completed rekey to a raw key
+        let key: JsValue = js_sys::Uint8Array::from(&[7u8; 32][..]).into();
+        let client = MLSClient::restore_with_encrypted_storage(
+            "user1".to_string(),
+            &state,
+            store.clone(),
+            JsValue::from_str("battery staple"),
+            None,
+        )
+        .unwrap();
+        client.rekey_storage(key.clone()).unwrap();
+        assert!(restore("battery staple").is_err());
+        let restored = MLSClient::restore_with_encrypted_storage("user1".to_string(), &state, store.clone(), key, None);
+        assert!(restored.unwrap().load_group(vec![155, 156, 157, 158]).is_ok());
+    }
+    
+    #[wasm_bindgen_test]
+    fn test_record_migration() {
+        let store = memory_store();
+        let client = MLSClient::with_storage("user1".to_string(), store.clone(), None, JsValue::UNDEFINED).unwrap();
+        let group_id = vec![175, 176, 177, 178];
+        client.create_group(group_id.clone()).unwrap();
+        let state = client.export_state().unwrap();
+        
+        let group_state = || {
+            stored_records(&store)
+                .into_iter()
+                .find(|record| record.get(0).as_string().unwrap() == "user1/group_state")
+                .unwrap()
+        };
+        let current = js_sys::Uint8Array::new(&group_state().get(2)).to_vec();
+        assert_eq!(current[0], 0xFF);
+        let payload = current[3..].to_vec();
+        
+        // Bare records load and are written back in the current layout
+        group_state().set(2, js_sys::Uint8Array::from(&payload[..]).into());
+        let restored = MLSClient::restore_with_storage("user1".to_string(), &state, store.clone(), None).unwrap();
+        assert_eq!(restored.load_group(group_id.clone()).unwrap().get_current_epoch().unwrap(), 0);
+        assert_eq!(js_sys::Uint8Array::new(&group_state().get(2)).to_vec(), current);
+        
+        // Records from a newer release are refused rather than misread
+        let newer = [&[0xFFu8, 0xFF, 0xFF][..], &payload].concat();
+        group_state().set(2, js_sys::Uint8Array::from(&newer[..]).into());
+        let error = client.load_group(group_id).err().unwrap();
+        assert!(error.to_string().contains("Unsupported storage version"), "{}", error);
+    }
+}